@@ -0,0 +1,442 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, Stream, StreamExt};
+use tokio::net::UdpSocket;
+use tokio::time;
+use uuid::Uuid;
+#[cfg(feature = "json")]
+use serde::Serialize;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+
+use crate::client::ClientError;
+use crate::constants;
+use crate::packet::{Packet, factory};
+use crate::packet::message::{ActiveTitle, GamepadButtons, MediaCommandResultData, MediaControlCommand, Message, MessageHeader};
+use crate::packet::message::{TextResult, TitleLaunchData};
+#[cfg(feature = "json")]
+use crate::packet::message::JsonData;
+use crate::packet::simple::DiscoveryResponseData;
+use crate::session::{Session, SessionError};
+use crate::session::channel::ServiceChannel;
+use crate::session::console::{ConsoleEvent, LaunchWatch};
+use crate::session::input;
+use crate::session::sensor::{Clock, SensorSample, SensorStream};
+use crate::session::text::TextPrompt;
+use crate::session::touch::{Gesture, TouchTarget};
+use crate::session::wait::{self, Inbox};
+use crate::sgcrypto::Crypto;
+use crate::state::SGState;
+
+/// A tokio based SmartGlass client talking to a single console over UDP
+///
+/// This is the async counterpart of `client::Client`, both drive the same `Session`.
+pub struct AsyncClient {
+    socket: UdpSocket,
+    console: SocketAddr,
+    session: Session,
+    inbox: Inbox
+}
+
+impl AsyncClient {
+    /// Creates a client for the console at `console`, bound to an ephemeral local port
+    pub async fn new(console: SocketAddr) -> io::Result<AsyncClient> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+        Ok(AsyncClient::with_socket(socket, console))
+    }
+
+    /// Creates a client using an already bound socket
+    pub fn with_socket(socket: UdpSocket, console: SocketAddr) -> AsyncClient {
+        AsyncClient {
+            socket,
+            console,
+            session: Session::new(),
+            inbox: Inbox::new()
+        }
+    }
+
+    pub fn console(&self) -> SocketAddr {
+        self.console
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn state(&self) -> &SGState {
+        self.session.state()
+    }
+
+    /// The oldest change to the console seen by the messages received so far
+    pub fn poll_event(&mut self) -> Option<ConsoleEvent> {
+        self.session.poll_event()
+    }
+
+    /// Asks the console to identify itself
+    pub async fn discover(&mut self, timeout: Duration) -> Result<DiscoveryResponseData, ClientError> {
+        self.send_packet(&factory::discovery_request(constants::CLIENT_TYPE)).await?;
+
+        let wait = async {
+            loop {
+                if let Packet::DiscoveryResponse(_, data) = self.recv().await? {
+                    return Ok::<_, ClientError>(data);
+                }
+            }
+        };
+
+        time::timeout(timeout, wait).await.map_err(|_| ClientError::Timeout)?
+    }
+
+    /// Discovers the console and performs the connect handshake
+    ///
+    /// # Arguments
+    /// * userhash - the Xbox Live userhash, empty for an anonymous connection
+    /// * jwt - the XSTS token, empty for an anonymous connection
+    /// * timeout - how long to wait for each of the console's responses
+    pub async fn connect(&mut self, userhash: String, jwt: String, timeout: Duration) -> Result<(), ClientError> {
+        let response = self.discover(timeout).await?;
+        let crypto = Crypto::from_certificate(&response.certificate).map_err(SessionError::from)?;
+        self.connect_with(crypto, Uuid::new_v4(), userhash, jwt, timeout).await
+    }
+
+    /// Performs the connect handshake using an already negotiated `Crypto`
+    ///
+    /// On failure the session is returned to `SGState::Disconnected`.
+    pub async fn connect_with(&mut self, crypto: Crypto, sg_uuid: Uuid, userhash: String, jwt: String, timeout: Duration) -> Result<(), ClientError> {
+        let result = time::timeout(timeout, self.handshake(crypto, sg_uuid, userhash, jwt)).await
+            .unwrap_or(Err(ClientError::Timeout));
+        if result.is_err() {
+            self.session.reset();
+        }
+        result
+    }
+
+    async fn handshake(&mut self, crypto: Crypto, sg_uuid: Uuid, userhash: String, jwt: String) -> Result<(), ClientError> {
+        // The console only answers the last request of the group
+        for request in self.session.connect_request(crypto, sg_uuid, userhash, jwt)? {
+            self.send_packet(&request).await?;
+        }
+
+        loop {
+            let packet = self.recv().await?;
+            if let Packet::ConnectResponse(..) = packet {
+                if self.session.handle_connect_response(&packet)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Tells the console we're leaving and drops the session state
+    pub async fn disconnect(&mut self) -> Result<(), ClientError> {
+        let packet = self.session.disconnect()?;
+        let result = self.send_packet(&packet).await;
+        self.session.reset();
+        result
+    }
+
+    /// Sends a message to the console on the given channel
+    ///
+    /// Messages with `need_ack` set are retransmitted by later `recv` calls
+    /// until the console acknowledges them.
+    pub async fn send(&mut self, message: Message, channel_id: u64, need_ack: bool) -> Result<(), ClientError> {
+        let datagrams = self.session.send_message(message, channel_id, need_ack, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Sends a message on the channel opened for `service`
+    pub async fn send_on(&mut self, service: ServiceChannel, message: Message, need_ack: bool) -> Result<(), ClientError> {
+        let datagrams = self.session.send_on(service, message, need_ack, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Sends `value` as a `Json` message on the channel for `service`
+    #[cfg(feature = "json")]
+    pub async fn send_json<T: Serialize>(&mut self, service: ServiceChannel, value: &T) -> Result<(), ClientError> {
+        let message = Message::Json(JsonData::from_value(value)?);
+        self.send_on(service, message, true).await
+    }
+
+    /// Presses and releases gamepad `buttons`
+    ///
+    /// Needs the `ServiceChannel::SystemInput` channel to be open.
+    pub async fn press(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.press(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Holds gamepad `buttons` down until `release` is called for them
+    pub async fn hold(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.hold(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Lets go of held gamepad `buttons`
+    pub async fn release(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.release(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Performs `gesture` on `target`, pacing the reports by their timestamps
+    ///
+    /// `TouchTarget::System` needs the `ServiceChannel::SystemInput` channel to be open,
+    /// `TouchTarget::Title(title_id)` the `ServiceChannel::Title(title_id)` one.
+    pub async fn touch(&mut self, gesture: &Gesture, target: TouchTarget) -> Result<(), ClientError> {
+        let start = Instant::now();
+        let reports = gesture.reports(input::timestamp() as u32);
+        let first = reports.first().map_or(0, |report| report.timestamp);
+
+        for report in reports {
+            let due = start + Duration::from_millis(u64::from(report.timestamp.wrapping_sub(first)));
+            time::sleep_until(time::Instant::from_std(due)).await;
+
+            let datagrams = self.session.touch(report, target, Instant::now())?;
+            self.send_datagrams(datagrams).await?;
+        }
+        Ok(())
+    }
+
+    /// Streams sensor `samples` to the title `title_id`, paced by `stream`
+    ///
+    /// Needs the `ServiceChannel::Title(title_id)` channel to be open.
+    /// Returns once `samples` ends.
+    pub async fn stream_sensor<S, C>(&mut self, title_id: u32, mut samples: S, stream: &mut SensorStream<C>) -> Result<(), ClientError>
+        where S: Stream<Item = SensorSample> + Unpin, C: Clock {
+        while let Some(sample) = samples.next().await {
+            time::sleep(stream.delay()).await;
+            if let Some(message) = stream.stamp(sample) {
+                let datagrams = self.session.sensor(title_id, message, Instant::now())?;
+                self.send_datagrams(datagrams).await?;
+            }
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Launches a title and waits up to `timeout` for it to show up
+    ///
+    /// Returns the launched title as the console reports it, see `LaunchWatch`
+    /// for how it is told apart from the titles that were running already.
+    pub async fn launch_title(&mut self, launch: TitleLaunchData, timeout: Duration) -> Result<ActiveTitle, ClientError> {
+        let mut watch = LaunchWatch::new(&launch, self.session.console());
+
+        let datagrams = self.session.launch_title(launch, Instant::now())?;
+        self.send_datagrams(datagrams).await?;
+
+        self.recv_matching(timeout, |message| watch.check(message)).await
+    }
+
+    /// Sends a media `command` to the title `title_id` and waits for the console's result
+    ///
+    /// Needs the `ServiceChannel::SystemMedia` channel to be open.
+    pub async fn media_command(&mut self, title_id: u32, command: MediaControlCommand, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        let (request_id, datagrams) = self.session.media_command(title_id, command, Instant::now())?;
+        self.send_datagrams(datagrams).await?;
+        self.recv_media_result(request_id, timeout).await
+    }
+
+    /// Seeks the media playing in `title_id` to `position` and waits for the console's result
+    pub async fn media_seek(&mut self, title_id: u32, position: Duration, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        let (request_id, datagrams) = self.session.media_seek(title_id, position, Instant::now())?;
+        self.send_datagrams(datagrams).await?;
+        self.recv_media_result(request_id, timeout).await
+    }
+
+    async fn recv_media_result(&mut self, request_id: u64, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        self.recv_matching(timeout, wait::media_result(request_id)).await
+    }
+
+    /// Waits up to `timeout` for the console to open an on-screen keyboard
+    ///
+    /// Returns right away if a keyboard is open already.
+    pub async fn text_prompt(&mut self, timeout: Duration) -> Result<TextPrompt, ClientError> {
+        if let Some(prompt) = self.session.text().prompt() {
+            return Ok(prompt.clone());
+        }
+
+        self.recv_matching(timeout, wait::text_prompt()).await
+    }
+
+    /// Types `text` into the open on-screen keyboard and closes it
+    ///
+    /// Waits up to `timeout` for the console to acknowledge the text before
+    /// accepting it. Needs the `ServiceChannel::SystemText` channel to be open.
+    pub async fn submit_text(&mut self, text: &str, timeout: Duration) -> Result<(), ClientError> {
+        let (version, datagrams) = self.session.submit_text(text, Instant::now())?;
+        self.send_datagrams(datagrams).await?;
+
+        self.recv_matching(timeout, wait::text_acknowledged(version)).await?;
+
+        let datagrams = self.session.finish_text(TextResult::Accept, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Waits up to `timeout` for a title to ask for text
+    ///
+    /// Returns right away if a title is waiting for text already.
+    pub async fn title_text_prompt(&mut self, timeout: Duration) -> Result<TextPrompt, ClientError> {
+        if let Some(prompt) = self.session.title_text().prompt() {
+            return Ok(prompt.clone());
+        }
+
+        self.recv_matching(timeout, wait::title_text_prompt()).await
+    }
+
+    /// Answers the title that asked for text
+    pub async fn title_text_input(&mut self, text: &str, result: TextResult) -> Result<(), ClientError> {
+        let datagrams = self.session.title_text_input(text, result, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Selects `length` bytes of the title's text starting at `start`
+    pub async fn title_text_select(&mut self, start: u32, length: u32) -> Result<(), ClientError> {
+        let datagrams = self.session.title_text_select(start, length, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Opens a channel for `service` and waits for the console to accept it
+    ///
+    /// Messages arriving in the meantime are kept for `recv`.
+    /// Returns the id of the new channel.
+    pub async fn open_channel(&mut self, service: ServiceChannel, timeout: Duration) -> Result<u64, ClientError> {
+        let (request_id, datagrams) = self.session.start_channel(service, Instant::now())?;
+        self.send_datagrams(datagrams).await?;
+
+        let started = self.recv_matching(timeout, wait::channel_started(service, request_id)).await?;
+        Ok(started?)
+    }
+
+    /// Closes the channel for `service`
+    pub async fn close_channel(&mut self, service: ServiceChannel) -> Result<(), ClientError> {
+        let datagrams = self.session.stop_channel(service, Instant::now())?;
+        self.send_datagrams(datagrams).await
+    }
+
+    /// Waits up to `timeout` for a message on the channel for `service`
+    ///
+    /// Messages for other channels are kept for `recv`.
+    pub async fn recv_on(&mut self, service: ServiceChannel, timeout: Duration) -> Result<(MessageHeader, Message), ClientError> {
+        let channel_id = self.session.channels().channel_id(service).ok_or(SessionError::ChannelNotOpen(service))?;
+
+        if let Some(found) = self.inbox.take_on(channel_id) {
+            return Ok(found);
+        }
+
+        let wait = async {
+            loop {
+                let packet = self.recv_packet().await?;
+                if let Some(found) = self.inbox.offer_on(packet, channel_id) {
+                    return Ok::<_, ClientError>(found);
+                }
+            }
+        };
+
+        time::timeout(timeout, wait).await.map_err(|_| ClientError::Timeout)?
+    }
+
+    /// Waits up to `timeout` for a `Json` message and parses it into `T`
+    ///
+    /// Messages the console split into JSON fragments are put back together
+    /// first, all other packets are kept for `recv`.
+    #[cfg(feature = "json")]
+    pub async fn recv_json<T: DeserializeOwned>(&mut self, timeout: Duration) -> Result<T, ClientError> {
+        let data = self.recv_matching(timeout, wait::json()).await?;
+        Ok(data.parse()?)
+    }
+
+    /// Waits up to `timeout` for a message `matches` picks something out of
+    ///
+    /// All other packets are kept for `recv`, up to `wait::MAX_INBOX` of them.
+    async fn recv_matching<T, F>(&mut self, timeout: Duration, mut matches: F) -> Result<T, ClientError>
+        where F: FnMut(&Message) -> Option<T> {
+        if let Some(found) = self.inbox.take_matching(&mut matches) {
+            return Ok(found);
+        }
+
+        let wait = async {
+            loop {
+                let packet = self.recv_packet().await?;
+                if let Some(found) = self.inbox.offer(packet, &mut matches) {
+                    return Ok::<_, ClientError>(found);
+                }
+            }
+        };
+
+        time::timeout(timeout, wait).await.map_err(|_| ClientError::Timeout)?
+    }
+
+    async fn send_datagrams(&self, datagrams: Vec<Vec<u8>>) -> Result<(), ClientError> {
+        for data in datagrams {
+            self.socket.send_to(&data, self.console).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends pending acknowledgements and retransmissions that are due
+    pub async fn flush(&mut self) -> Result<(), ClientError> {
+        while let Some(data) = self.session.poll_transmit(Instant::now())? {
+            self.socket.send_to(&data, self.console).await?;
+        }
+        Ok(())
+    }
+
+    /// Serializes and sends a single packet to the console
+    pub async fn send_packet(&self, packet: &Packet) -> Result<(), ClientError> {
+        let data = self.session.raw_bytes(packet)?;
+        self.socket.send_to(&data, self.console).await?;
+        Ok(())
+    }
+
+    /// Waits until the console sends us a packet
+    pub async fn recv(&mut self) -> Result<Packet, ClientError> {
+        if let Some(packet) = self.inbox.pop() {
+            return Ok(packet);
+        }
+        self.recv_packet().await
+    }
+
+    async fn recv_packet(&mut self) -> Result<Packet, ClientError> {
+        let mut buf = [0u8; 2048];
+        loop {
+            self.flush().await?;
+
+            // Wake up for retransmissions while waiting
+            let received = match self.session.poll_timeout() {
+                Some(wake) => match time::timeout_at(time::Instant::from_std(wake), self.socket.recv_from(&mut buf)).await {
+                    Ok(received) => received,
+                    Err(_) => continue
+                },
+                None => self.socket.recv_from(&mut buf).await
+            };
+            let (len, addr) = received?;
+
+            // Anything not coming from our console is just noise on the port
+            if addr != self.console {
+                continue;
+            }
+
+            if let Some(packet) = self.session.receive(&buf[..len])? {
+                self.flush().await?;
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Turns the client into a stream of the messages the console sends us
+    ///
+    /// Packets that aren't messages are skipped, errors are passed through
+    /// without ending the stream.
+    pub fn messages(&mut self) -> impl Stream<Item = Result<(MessageHeader, Message), ClientError>> + '_ {
+        stream::unfold(self, |client| async move {
+            loop {
+                match client.recv().await {
+                    Ok(Packet::Message(header, message)) => return Some((Ok((header, message)), client)),
+                    Ok(_) => continue,
+                    Err(err) => return Some((Err(err), client))
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,498 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+#[cfg(feature = "json")]
+use serde::Serialize;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+
+use crate::constants;
+use crate::packet::{Packet, factory};
+use crate::packet::message::{ActiveTitle, GamepadButtons, MediaCommandResultData, MediaControlCommand, Message, MessageHeader};
+use crate::packet::message::{TextResult, TitleLaunchData};
+#[cfg(feature = "json")]
+use crate::packet::message::JsonData;
+use crate::packet::simple::DiscoveryResponseData;
+use crate::session::{Session, SessionError};
+use crate::session::channel::ServiceChannel;
+use crate::session::console::{ConsoleEvent, LaunchWatch};
+use crate::session::input;
+use crate::session::sensor::{Clock, SensorSample, SensorStream};
+use crate::session::text::TextPrompt;
+use crate::session::touch::{Gesture, TouchTarget};
+use crate::session::wait::{self, Inbox};
+use crate::sgcrypto::Crypto;
+use crate::state::SGState;
+
+// Written out instead of using quick_error!, which can't leave the impls of
+// the feature gated `Json` variant out of builds without the feature
+#[derive(Debug)]
+pub enum ClientError {
+    IO(io::Error),
+    Session(SessionError),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    Timeout
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::IO(ref err) => write!(f, "{}", err),
+            ClientError::Session(ref err) => write!(f, "{}", err),
+            #[cfg(feature = "json")]
+            ClientError::Json(ref err) => write!(f, "{}", err),
+            ClientError::Timeout => write!(f, "Timed out waiting for the console")
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ClientError::IO(ref err) => Some(err),
+            ClientError::Session(ref err) => Some(err),
+            #[cfg(feature = "json")]
+            ClientError::Json(ref err) => Some(err),
+            ClientError::Timeout => None
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::IO(err)
+    }
+}
+
+impl From<SessionError> for ClientError {
+    fn from(err: SessionError) -> Self {
+        ClientError::Session(err)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ClientError::Json(err)
+    }
+}
+
+/// A blocking SmartGlass client talking to a single console over UDP
+pub struct Client {
+    socket: UdpSocket,
+    console: SocketAddr,
+    session: Session,
+    inbox: Inbox
+}
+
+impl Client {
+    /// Creates a client for the console at `console`, bound to an ephemeral local port
+    pub fn new(console: SocketAddr) -> io::Result<Client> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        Ok(Client::with_socket(socket, console))
+    }
+
+    /// Creates a client using an already bound socket
+    pub fn with_socket(socket: UdpSocket, console: SocketAddr) -> Client {
+        Client {
+            socket,
+            console,
+            session: Session::new(),
+            inbox: Inbox::new()
+        }
+    }
+
+    pub fn console(&self) -> SocketAddr {
+        self.console
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn state(&self) -> &SGState {
+        self.session.state()
+    }
+
+    /// The oldest change to the console seen by the messages received so far
+    pub fn poll_event(&mut self) -> Option<ConsoleEvent> {
+        self.session.poll_event()
+    }
+
+    /// Asks the console to identify itself
+    pub fn discover(&mut self, timeout: Duration) -> Result<DiscoveryResponseData, ClientError> {
+        let deadline = Instant::now() + timeout;
+        self.send(&factory::discovery_request(constants::CLIENT_TYPE))?;
+
+        loop {
+            if let Packet::DiscoveryResponse(_, data) = self.recv_until(deadline)? {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// Discovers the console and performs the connect handshake
+    ///
+    /// # Arguments
+    /// * userhash - the Xbox Live userhash, empty for an anonymous connection
+    /// * jwt - the XSTS token, empty for an anonymous connection
+    /// * timeout - how long to wait for each of the console's responses
+    pub fn connect(&mut self, userhash: String, jwt: String, timeout: Duration) -> Result<(), ClientError> {
+        let response = self.discover(timeout)?;
+        let crypto = Crypto::from_certificate(&response.certificate).map_err(SessionError::from)?;
+        self.connect_with(crypto, Uuid::new_v4(), userhash, jwt, timeout)
+    }
+
+    /// Performs the connect handshake using an already negotiated `Crypto`
+    ///
+    /// On failure the session is returned to `SGState::Disconnected`.
+    pub fn connect_with(&mut self, crypto: Crypto, sg_uuid: Uuid, userhash: String, jwt: String, timeout: Duration) -> Result<(), ClientError> {
+        let result = self.handshake(crypto, sg_uuid, userhash, jwt, timeout);
+        if result.is_err() {
+            self.session.reset();
+        }
+        result
+    }
+
+    fn handshake(&mut self, crypto: Crypto, sg_uuid: Uuid, userhash: String, jwt: String, timeout: Duration) -> Result<(), ClientError> {
+        let deadline = Instant::now() + timeout;
+        // The console only answers the last request of the group
+        for request in self.session.connect_request(crypto, sg_uuid, userhash, jwt)? {
+            self.send(&request)?;
+        }
+
+        loop {
+            let packet = self.recv_until(deadline)?;
+            if let Packet::ConnectResponse(..) = packet {
+                if self.session.handle_connect_response(&packet)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Tells the console we're leaving and drops the session state
+    pub fn disconnect(&mut self) -> Result<(), ClientError> {
+        let packet = self.session.disconnect()?;
+        let result = self.send(&packet);
+        self.session.reset();
+        result
+    }
+
+    /// Sends a message to the console on the given channel
+    ///
+    /// Messages with `need_ack` set are retransmitted by later `recv` calls
+    /// until the console acknowledges them.
+    pub fn send_message(&mut self, message: Message, channel_id: u64, need_ack: bool) -> Result<(), ClientError> {
+        let datagrams = self.session.send_message(message, channel_id, need_ack, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Sends a message on the channel opened for `service`
+    pub fn send_on(&mut self, service: ServiceChannel, message: Message, need_ack: bool) -> Result<(), ClientError> {
+        let datagrams = self.session.send_on(service, message, need_ack, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Sends `value` as a `Json` message on the channel for `service`
+    #[cfg(feature = "json")]
+    pub fn send_json<T: Serialize>(&mut self, service: ServiceChannel, value: &T) -> Result<(), ClientError> {
+        let message = Message::Json(JsonData::from_value(value)?);
+        self.send_on(service, message, true)
+    }
+
+    /// Presses and releases gamepad `buttons`
+    ///
+    /// Needs the `ServiceChannel::SystemInput` channel to be open.
+    pub fn press(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.press(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Holds gamepad `buttons` down until `release` is called for them
+    pub fn hold(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.hold(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Lets go of held gamepad `buttons`
+    pub fn release(&mut self, buttons: GamepadButtons) -> Result<(), ClientError> {
+        let datagrams = self.session.release(buttons, input::timestamp(), Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Performs `gesture` on `target`, pacing the reports by their timestamps
+    ///
+    /// `TouchTarget::System` needs the `ServiceChannel::SystemInput` channel to be open,
+    /// `TouchTarget::Title(title_id)` the `ServiceChannel::Title(title_id)` one.
+    pub fn touch(&mut self, gesture: &Gesture, target: TouchTarget) -> Result<(), ClientError> {
+        let start = Instant::now();
+        let reports = gesture.reports(input::timestamp() as u32);
+        let first = reports.first().map_or(0, |report| report.timestamp);
+
+        for report in reports {
+            let due = start + Duration::from_millis(u64::from(report.timestamp.wrapping_sub(first)));
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+
+            let datagrams = self.session.touch(report, target, Instant::now())?;
+            self.send_datagrams(datagrams)?;
+        }
+        Ok(())
+    }
+
+    /// Streams sensor `samples` to the title `title_id`, paced by `stream`
+    ///
+    /// Samples can come from any iterator, like the one of an `mpsc::Receiver`.
+    /// Needs the `ServiceChannel::Title(title_id)` channel to be open.
+    /// Returns once `samples` ends.
+    pub fn stream_sensor<I, C>(&mut self, title_id: u32, samples: I, stream: &mut SensorStream<C>) -> Result<(), ClientError>
+        where I: IntoIterator<Item = SensorSample>, C: Clock {
+        for sample in samples {
+            thread::sleep(stream.delay());
+            if let Some(message) = stream.stamp(sample) {
+                let datagrams = self.session.sensor(title_id, message, Instant::now())?;
+                self.send_datagrams(datagrams)?;
+            }
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Launches a title and waits up to `timeout` for it to show up
+    ///
+    /// Returns the launched title as the console reports it, see `LaunchWatch`
+    /// for how it is told apart from the titles that were running already.
+    pub fn launch_title(&mut self, launch: TitleLaunchData, timeout: Duration) -> Result<ActiveTitle, ClientError> {
+        let mut watch = LaunchWatch::new(&launch, self.session.console());
+
+        let datagrams = self.session.launch_title(launch, Instant::now())?;
+        self.send_datagrams(datagrams)?;
+
+        self.recv_matching(Instant::now() + timeout, |message| watch.check(message))
+    }
+
+    /// Sends a media `command` to the title `title_id` and waits for the console's result
+    ///
+    /// Needs the `ServiceChannel::SystemMedia` channel to be open.
+    pub fn media_command(&mut self, title_id: u32, command: MediaControlCommand, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        let (request_id, datagrams) = self.session.media_command(title_id, command, Instant::now())?;
+        self.send_datagrams(datagrams)?;
+        self.recv_media_result(request_id, timeout)
+    }
+
+    /// Seeks the media playing in `title_id` to `position` and waits for the console's result
+    pub fn media_seek(&mut self, title_id: u32, position: Duration, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        let (request_id, datagrams) = self.session.media_seek(title_id, position, Instant::now())?;
+        self.send_datagrams(datagrams)?;
+        self.recv_media_result(request_id, timeout)
+    }
+
+    fn recv_media_result(&mut self, request_id: u64, timeout: Duration) -> Result<MediaCommandResultData, ClientError> {
+        self.recv_matching(Instant::now() + timeout, wait::media_result(request_id))
+    }
+
+    /// Waits up to `timeout` for the console to open an on-screen keyboard
+    ///
+    /// Returns right away if a keyboard is open already.
+    pub fn text_prompt(&mut self, timeout: Duration) -> Result<TextPrompt, ClientError> {
+        if let Some(prompt) = self.session.text().prompt() {
+            return Ok(prompt.clone());
+        }
+
+        self.recv_matching(Instant::now() + timeout, wait::text_prompt())
+    }
+
+    /// Types `text` into the open on-screen keyboard and closes it
+    ///
+    /// Waits up to `timeout` for the console to acknowledge the text before
+    /// accepting it. Needs the `ServiceChannel::SystemText` channel to be open.
+    pub fn submit_text(&mut self, text: &str, timeout: Duration) -> Result<(), ClientError> {
+        let (version, datagrams) = self.session.submit_text(text, Instant::now())?;
+        self.send_datagrams(datagrams)?;
+
+        self.recv_matching(Instant::now() + timeout, wait::text_acknowledged(version))?;
+
+        let datagrams = self.session.finish_text(TextResult::Accept, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Waits up to `timeout` for a title to ask for text
+    ///
+    /// Returns right away if a title is waiting for text already.
+    pub fn title_text_prompt(&mut self, timeout: Duration) -> Result<TextPrompt, ClientError> {
+        if let Some(prompt) = self.session.title_text().prompt() {
+            return Ok(prompt.clone());
+        }
+
+        self.recv_matching(Instant::now() + timeout, wait::title_text_prompt())
+    }
+
+    /// Answers the title that asked for text
+    pub fn title_text_input(&mut self, text: &str, result: TextResult) -> Result<(), ClientError> {
+        let datagrams = self.session.title_text_input(text, result, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Selects `length` bytes of the title's text starting at `start`
+    pub fn title_text_select(&mut self, start: u32, length: u32) -> Result<(), ClientError> {
+        let datagrams = self.session.title_text_select(start, length, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Opens a channel for `service` and waits for the console to accept it
+    ///
+    /// Messages arriving in the meantime are kept for `recv`.
+    /// Returns the id of the new channel.
+    pub fn open_channel(&mut self, service: ServiceChannel, timeout: Duration) -> Result<u64, ClientError> {
+        let (request_id, datagrams) = self.session.start_channel(service, Instant::now())?;
+        self.send_datagrams(datagrams)?;
+
+        let started = self.recv_matching(Instant::now() + timeout, wait::channel_started(service, request_id))?;
+        Ok(started?)
+    }
+
+    /// Closes the channel for `service`
+    pub fn close_channel(&mut self, service: ServiceChannel) -> Result<(), ClientError> {
+        let datagrams = self.session.stop_channel(service, Instant::now())?;
+        self.send_datagrams(datagrams)
+    }
+
+    /// Waits up to `timeout` for a message on the channel for `service`
+    ///
+    /// Messages for other channels are kept for `recv`.
+    pub fn recv_on(&mut self, service: ServiceChannel, timeout: Duration) -> Result<(MessageHeader, Message), ClientError> {
+        let channel_id = self.session.channels().channel_id(service).ok_or(SessionError::ChannelNotOpen(service))?;
+
+        if let Some(found) = self.inbox.take_on(channel_id) {
+            return Ok(found);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let packet = self.recv_until(deadline)?;
+            if let Some(found) = self.inbox.offer_on(packet, channel_id) {
+                return Ok(found);
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for a `Json` message and parses it into `T`
+    ///
+    /// Messages the console split into JSON fragments are put back together
+    /// first, all other packets are kept for `recv`.
+    #[cfg(feature = "json")]
+    pub fn recv_json<T: DeserializeOwned>(&mut self, timeout: Duration) -> Result<T, ClientError> {
+        let data = self.recv_matching(Instant::now() + timeout, wait::json())?;
+        Ok(data.parse()?)
+    }
+
+    /// Waits until `deadline` for a message `matches` picks something out of
+    ///
+    /// All other packets are kept for `recv`, up to `wait::MAX_INBOX` of them.
+    fn recv_matching<T, F>(&mut self, deadline: Instant, mut matches: F) -> Result<T, ClientError>
+        where F: FnMut(&Message) -> Option<T> {
+        if let Some(found) = self.inbox.take_matching(&mut matches) {
+            return Ok(found);
+        }
+
+        loop {
+            let packet = self.recv_until(deadline)?;
+            if let Some(found) = self.inbox.offer(packet, &mut matches) {
+                return Ok(found);
+            }
+        }
+    }
+
+    fn send_datagrams(&self, datagrams: Vec<Vec<u8>>) -> Result<(), ClientError> {
+        for data in datagrams {
+            self.socket.send_to(&data, self.console)?;
+        }
+        Ok(())
+    }
+
+    /// Sends pending acknowledgements and retransmissions that are due
+    pub fn flush(&mut self) -> Result<(), ClientError> {
+        while let Some(data) = self.session.poll_transmit(Instant::now())? {
+            self.socket.send_to(&data, self.console)?;
+        }
+        Ok(())
+    }
+
+    /// Serializes and sends a single packet to the console
+    pub fn send(&self, packet: &Packet) -> Result<(), ClientError> {
+        let data = self.session.raw_bytes(packet)?;
+        self.socket.send_to(&data, self.console)?;
+        Ok(())
+    }
+
+    /// Blocks until the console sends us a packet
+    pub fn recv(&mut self) -> Result<Packet, ClientError> {
+        if let Some(packet) = self.inbox.pop() {
+            return Ok(packet);
+        }
+        self.recv_packet(None)
+    }
+
+    /// Waits up to `timeout` for the console to send us a packet
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Packet, ClientError> {
+        if let Some(packet) = self.inbox.pop() {
+            return Ok(packet);
+        }
+        self.recv_packet(Some(Instant::now() + timeout))
+    }
+
+    fn recv_until(&mut self, deadline: Instant) -> Result<Packet, ClientError> {
+        self.recv_packet(Some(deadline))
+    }
+
+    fn recv_packet(&mut self, deadline: Option<Instant>) -> Result<Packet, ClientError> {
+        let mut buf = [0u8; 2048];
+        loop {
+            self.flush()?;
+
+            let now = Instant::now();
+            if let Some(deadline) = deadline {
+                if now >= deadline {
+                    return Err(ClientError::Timeout);
+                }
+            }
+
+            // Wake up for retransmissions even if the caller is willing to wait longer
+            let wake = match (deadline, self.session.poll_timeout()) {
+                (Some(deadline), Some(retransmit)) => Some(deadline.min(retransmit)),
+                (deadline, retransmit) => deadline.or(retransmit)
+            };
+            let timeout = wake.map(|wake| wake.saturating_duration_since(now).max(Duration::from_millis(1)));
+            self.socket.set_read_timeout(timeout)?;
+
+            let (len, addr) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref err) if is_timeout(err) => continue,
+                Err(err) => return Err(err.into())
+            };
+
+            // Anything not coming from our console is just noise on the port
+            if addr != self.console {
+                continue;
+            }
+
+            if let Some(packet) = self.session.receive(&buf[..len])? {
+                self.flush()?;
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
@@ -1,6 +1,6 @@
 pub mod uuid {
     use uuid::Uuid;
-    use ::util::UUID;
+    use crate::util::UUID;
     lazy_static! {
         pub static ref NONE: UUID<u8> = UUID::<u8>::new(Uuid::parse_str("00000000-0000-0000-0000-000000000000").unwrap());
         pub static ref SYSTEM_INPUT: UUID<u8> = UUID::<u8>::new(Uuid::parse_str("fa20b8ca-66fb-46e0-adb6-0b978a59d35f").unwrap());
@@ -9,4 +9,23 @@ pub mod uuid {
         pub static ref SYSTEM_TEXT: UUID<u8> = UUID::<u8>::new(Uuid::parse_str("7af3e6a2-488b-40cb-a931-79c04b7da3a0").unwrap());
         pub static ref SYSTEM_BROADCAST: UUID<u8> = UUID::<u8>::new(Uuid::parse_str("b6a117d8-f5e2-45d7-862e-8fd8e3156476").unwrap());
     }
-}
\ No newline at end of file
+}
+
+/// The UDP port consoles listen on
+pub const PORT: u16 = 5050;
+
+/// The client type we announce ourselves as during discovery (Android)
+pub const CLIENT_TYPE: u16 = 0x8;
+
+pub mod channel {
+    /// Channel used for messages that don't belong to a service channel
+    pub const CORE: u64 = 0x0;
+    /// Channel used for acknowledgements
+    pub const ACK: u64 = 0x1000000000000000;
+}
+
+pub mod connect_result {
+    pub const SUCCESS: u16 = 0x0;
+    /// The console is still waiting for the rest of a connect request group
+    pub const PENDING: u16 = 0x1;
+}
@@ -0,0 +1,221 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::packet::{Packet, WriteError, factory};
+use crate::state::SGState;
+use crate::util::{Certificate, UUID};
+
+/// How many power on requests are sent to each target
+const POWER_ON_ATTEMPTS: u32 = 5;
+/// The delay between two power on requests
+const POWER_ON_INTERVAL: Duration = Duration::from_millis(100);
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum DiscoveryError {
+        IO(err: io::Error) { from() }
+        Write(err: WriteError) { from() }
+    }
+}
+
+/// A console that answered a discovery request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Console {
+    pub name: String,
+    pub uuid: UUID<String>,
+    pub address: SocketAddr,
+    pub client_type: u16,
+    pub certificate: Certificate
+}
+
+/// Looks for consoles on the network
+///
+/// Discovery requests are sent to the broadcast address and to every unicast
+/// address repeatedly until the timeout expires. Every console is reported
+/// once, no matter how many of the requests it answered.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    broadcast: Option<SocketAddr>,
+    unicast: Vec<SocketAddr>,
+    timeout: Duration,
+    interval: Duration
+}
+
+impl Discovery {
+    pub fn new() -> Self {
+        Discovery {
+            broadcast: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), constants::PORT)),
+            unicast: Vec::new(),
+            timeout: Duration::from_secs(3),
+            interval: Duration::from_millis(500)
+        }
+    }
+
+    /// Only send requests to the unicast addresses
+    pub fn without_broadcast(mut self) -> Self {
+        self.broadcast = None;
+        self
+    }
+
+    /// Also send requests directly to `addr`
+    pub fn unicast(mut self, addr: SocketAddr) -> Self {
+        self.unicast.push(addr);
+        self
+    }
+
+    /// How long to wait for responses in total
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long to wait between two rounds of requests
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Scans for consoles using a socket bound to an ephemeral port
+    pub fn scan(&self) -> Result<Vec<Console>, DiscoveryError> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        self.scan_with_socket(&socket)
+    }
+
+    /// Scans for consoles using an already bound socket
+    pub fn scan_with_socket(&self, socket: &UdpSocket) -> Result<Vec<Console>, DiscoveryError> {
+        let mut consoles: Vec<Console> = Vec::new();
+        self.poll(socket, |console| {
+            if !consoles.iter().any(|known| known.uuid == console.uuid) {
+                consoles.push(console);
+            }
+            false
+        })?;
+
+        Ok(consoles)
+    }
+
+    /// Wakes the console with the given Live ID and waits for it to show up
+    ///
+    /// A burst of power on requests is sent to the broadcast and unicast
+    /// addresses, then discovery is polled until a console whose certificate
+    /// matches `live_id` answers or the timeout expires.
+    ///
+    /// Returns whether the console answered.
+    pub fn power_on(&self, live_id: &str) -> Result<bool, DiscoveryError> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        self.power_on_with_socket(&socket, live_id)
+    }
+
+    /// Wakes a console using an already bound socket, see `power_on`
+    pub fn power_on_with_socket(&self, socket: &UdpSocket, live_id: &str) -> Result<bool, DiscoveryError> {
+        let request = factory::power_on_request(live_id.to_string()).raw_bytes(&SGState::Disconnected)?;
+
+        if self.broadcast.is_some() {
+            socket.set_broadcast(true)?;
+        }
+
+        for attempt in 0..POWER_ON_ATTEMPTS {
+            if attempt > 0 {
+                thread::sleep(POWER_ON_INTERVAL);
+            }
+            for target in self.broadcast.iter().chain(self.unicast.iter()) {
+                socket.send_to(&request, target)?;
+            }
+        }
+
+        let mut answered = false;
+        self.poll(socket, |console| {
+            answered = console.certificate.subject() == live_id;
+            answered
+        })?;
+
+        Ok(answered)
+    }
+
+    /// Sends requests until the timeout expires or `found` returns true
+    fn poll<F>(&self, socket: &UdpSocket, mut found: F) -> Result<(), DiscoveryError>
+        where F: FnMut(Console) -> bool {
+        let request = factory::discovery_request(constants::CLIENT_TYPE).raw_bytes(&SGState::Disconnected)?;
+
+        if self.broadcast.is_some() {
+            socket.set_broadcast(true)?;
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut next_request = Instant::now();
+        let mut buf = [0u8; 2048];
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+
+            if now >= next_request {
+                for target in self.broadcast.iter().chain(self.unicast.iter()) {
+                    socket.send_to(&request, target)?;
+                }
+                next_request = now + self.interval;
+            }
+
+            let wait = if next_request < deadline { next_request } else { deadline };
+            socket.set_read_timeout(Some(wait.saturating_duration_since(now).max(Duration::from_millis(1))))?;
+
+            let (len, address) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err.into())
+            };
+
+            // Other clients' requests and garbage show up here too, skip them
+            if let Ok(Packet::DiscoveryResponse(_, data)) = Packet::read(&buf[..len], &SGState::Disconnected) {
+                let console = Console {
+                    name: data.name.to_str(),
+                    uuid: data.uuid,
+                    address,
+                    client_type: data.client_type,
+                    certificate: data.certificate
+                };
+
+                if found(console) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Wakes the console with the given Live ID at `addr`
+///
+/// Power on requests go to the broadcast address as well, since a sleeping
+/// console may not answer on its last known address.
+/// Returns whether the console answered a discovery request before `timeout`.
+pub fn power_on(live_id: &str, addr: SocketAddr, timeout: Duration) -> Result<bool, DiscoveryError> {
+    Discovery::new()
+        .unicast(addr)
+        .timeout(timeout)
+        .power_on(live_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builder_works() {
+        let addr = "192.168.0.2:5050".parse().unwrap();
+        let discovery = Discovery::new()
+            .without_broadcast()
+            .unicast(addr)
+            .timeout(Duration::from_secs(1))
+            .interval(Duration::from_millis(100));
+
+        assert_eq!(discovery.broadcast, None);
+        assert_eq!(discovery.unicast, vec![addr]);
+        assert_eq!(discovery.timeout, Duration::from_secs(1));
+        assert_eq!(discovery.interval, Duration::from_millis(100));
+    }
+}
@@ -1,6 +1,6 @@
 #[macro_use]
 extern crate protocol_derive;
-#[macro_use(define_composite_type, implement_composite_type)]
+#[macro_use(define_composite_type)]
 extern crate protocol;
 #[macro_use(quick_error)]
 extern crate quick_error;
@@ -8,13 +8,27 @@ extern crate quick_error;
 extern crate enum_primitive_derive;
 extern crate num_traits;
 extern crate bit_field;
+#[macro_use]
+extern crate bitflags;
 extern crate uuid;
 #[macro_use]
 extern crate lazy_static;
+extern crate zeroize;
+#[cfg(feature = "json")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
 
 
 pub mod sgcrypto;
 pub mod packet;
 pub mod util;
 pub mod state;
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod session;
+pub mod client;
+pub mod discovery;
+#[cfg(feature = "async")]
+pub mod async_client;
+#[cfg(feature = "mock")]
+pub mod mock;
\ No newline at end of file
@@ -0,0 +1,420 @@
+use std::io::{self, Cursor};
+use std::str::FromStr;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+use const_oid::db::rfc5912::ID_EC_PUBLIC_KEY;
+use p256::ecdsa::{DerSignature, SigningKey};
+use protocol::{DynArray, Parcel};
+use rand_core::OsRng;
+use uuid::Uuid;
+use x509_cert::builder::{self, Builder, CertificateBuilder, Profile};
+use x509_cert::der::{self, Any, Encode};
+use x509_cert::der::asn1::BitString;
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use x509_cert::time::Validity;
+
+use crate::constants;
+use crate::packet::{Packet, ReadError, Type, WriteError};
+use crate::packet::message::*;
+use crate::packet::simple::*;
+use crate::session::ack::AckTracker;
+use crate::sgcrypto::{self, Crypto};
+use crate::state::*;
+use crate::util::{Certificate, PublicKeyType, SGString, UUID};
+
+/// The participant id the mock hands out to its client
+pub const PARTICIPANT_ID: u32 = 31;
+/// The title id the mock gives titles launched by a URI without one
+pub const LAUNCHED_TITLE_ID: u32 = 0x1234_5678;
+/// The id of the first channel the mock opens, further ones count up from here
+const FIRST_CHANNEL_ID: u64 = 148;
+/// How often the server thread checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum MockError {
+        Crypto(err: sgcrypto::Error) { from() }
+        IO(err: io::Error) { from() }
+        Certificate(err: builder::Error) { from() }
+        Der(err: der::Error) { from() }
+        Protocol(err: protocol::Error) { from() }
+        Read(err: ReadError) { from() }
+        State(err: InvalidState) { from() }
+        UnknownPacket {
+            display("Not a SmartGlass packet")
+        }
+        UnexpectedPacket(pkt_type: Type) {
+            display("Unexpected packet: {:?}", pkt_type)
+        }
+        Write(err: WriteError) { from() }
+    }
+}
+
+/// The console side of the protocol, for testing clients without a console
+///
+/// Answers discovery requests with a certificate for a freshly generated
+/// P-256 key (or one on the curve given to `with_key_type`), derives the session keys from the client's connect request and
+/// greets the client with a `ConsoleStatus` once connected. Messages asking
+/// for it are acknowledged, channel requests are granted and launched titles
+/// show up in a new `ConsoleStatus`. Retransmitted messages are acknowledged
+/// again but not handled twice.
+///
+/// Like `Session` this doesn't own a socket, `spawn` runs it on loopback.
+/// The server drops datagrams it can't handle like a console would, the
+/// error is kept for `last_error`.
+pub struct MockConsole {
+    name: String,
+    uuid: Uuid,
+    key_type: PublicKeyType,
+    /// The private key of the certificate as a big endian number
+    key: Vec<u8>,
+    certificate: Certificate,
+    status: ConsoleStatusData,
+    state: SGState,
+    sequence_number: u32,
+    acks: AckTracker,
+    next_channel_id: u64,
+    userhash: String,
+    jwt: String,
+    last_error: Option<MockError>
+}
+
+impl MockConsole {
+    pub fn new() -> Result<Self, MockError> {
+        MockConsole::with_key_type(PublicKeyType::EcDhP256)
+    }
+
+    /// A console whose certificate holds a key on the curve of `key_type`
+    pub fn with_key_type(key_type: PublicKeyType) -> Result<Self, MockError> {
+        let uuid = Uuid::new_v4();
+        let (key, public_key) = sgcrypto::generate_key_pair(key_type);
+        let certificate = MockConsole::certificate(key_type, &public_key, "FFFFFFFFFFF")?;
+
+        Ok(MockConsole {
+            name: String::from("XboxOne"),
+            uuid,
+            key_type,
+            key,
+            certificate,
+            status: ConsoleStatusData {
+                live_tv_provider: 0,
+                major_version: 10,
+                minor_version: 0,
+                build_number: 14393,
+                locale: SGString::from_str(String::from("en-US")),
+                active_titles: DynArray::new(vec![ActiveTitle {
+                    title_id: 714681658,
+                    title_disposition: TitleDisposition {
+                        has_focus: true,
+                        location: TitleLocation::StartView
+                    },
+                    product_id: constants::uuid::NONE.clone(),
+                    sandbox_id: constants::uuid::NONE.clone(),
+                    aum: SGString::from_str(String::from("Xbox.Home_8wekyb3d8bbwe!Xbox.Home.Application"))
+                }])
+            },
+            state: SGState::Disconnected,
+            sequence_number: 0,
+            acks: AckTracker::new(),
+            next_channel_id: FIRST_CHANNEL_ID,
+            userhash: String::new(),
+            jwt: String::new(),
+            last_error: None
+        })
+    }
+
+    /// The name the console announces itself with
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// The status sent to the client after it connected
+    pub fn status(mut self, status: ConsoleStatusData) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    pub fn state(&self) -> &SGState {
+        &self.state
+    }
+
+    /// The credentials of the last connect request group, the jwt put back together
+    pub fn credentials(&self) -> (&str, &str) {
+        (&self.userhash, &self.jwt)
+    }
+
+    /// Why the last datagram the server dropped couldn't be handled
+    pub fn last_error(&self) -> Option<&MockError> {
+        self.last_error.as_ref()
+    }
+
+    /// A certificate for `public_key`, an uncompressed point on the curve of `key_type`
+    fn certificate(key_type: PublicKeyType, public_key: &[u8], subject: &str) -> Result<Certificate, MockError> {
+        let key_info = SubjectPublicKeyInfoOwned {
+            algorithm: AlgorithmIdentifierOwned {
+                oid: ID_EC_PUBLIC_KEY,
+                parameters: Some(Any::encode_from(&key_type.oid())?)
+            },
+            subject_public_key: BitString::from_bytes(public_key)?
+        };
+
+        // Clients don't check the signature, so a throwaway P-256 key signs for every curve
+        let signer = SigningKey::random(&mut OsRng);
+        let name = Name::from_str(&format!("CN={}", subject))?;
+        let validity = Validity::from_now(Duration::from_secs(365 * 24 * 60 * 60))?;
+        let builder = CertificateBuilder::new(Profile::Root, SerialNumber::from(1u32), validity, name, key_info, &signer)?;
+
+        let der = DynArray::<u16, u8>::new(builder.build::<DerSignature>()?.to_der()?);
+        Ok(Certificate::from_raw_bytes(&der.raw_bytes()?)?)
+    }
+
+    /// Runs the console on a loopback UDP socket in a thread of its own
+    pub fn spawn(self) -> io::Result<MockServer> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = socket.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || MockServer::run(self, socket, &stop))
+        };
+
+        Ok(MockServer {
+            addr,
+            stop,
+            thread: Some(thread)
+        })
+    }
+
+    /// Handles a datagram from the client, returning the datagrams to answer with
+    pub fn handle(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, MockError> {
+        let pkt_type = data.get(..2)
+            .and_then(|id| Type::from_u16(u16::from(id[0]) << 8 | u16::from(id[1])))
+            .ok_or(MockError::UnknownPacket)?;
+
+        match pkt_type {
+            Type::DiscoveryRequest => self.handle_discovery(data),
+            Type::ConnectRequest => self.handle_connect(data),
+            Type::Message => self.handle_message(data),
+            other => Err(MockError::UnexpectedPacket(other))
+        }
+    }
+
+    fn handle_discovery(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, MockError> {
+        Packet::read(data, &SGState::Disconnected)?;
+
+        let response = Packet::DiscoveryResponse(
+            SimpleHeader::new(Type::DiscoveryResponse, 2),
+            DiscoveryResponseData {
+                flags: 2,
+                client_type: 1,
+                name: SGString::from_str(self.name.clone()),
+                uuid: UUID::new(self.uuid),
+                padding: [0u8; 5],
+                certificate: self.certificate.clone()
+            }
+        );
+
+        Ok(vec![response.raw_bytes(&SGState::Disconnected)?])
+    }
+
+    fn handle_connect(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, MockError> {
+        // The keys depend on the client's public key, which isn't protected
+        let mut reader = Cursor::new(data);
+        SimpleHeader::read(&mut reader)?;
+        let unprotected = ConnectRequestUnprotectedData::read(&mut reader)?;
+
+        let connecting = match self.state {
+            SGState::Connected(ref state) => state.connection_state == ConnectionState::Connecting,
+            SGState::Disconnected => false
+        };
+        // The rest of a group is protected by the keys of its first request
+        if !connecting {
+            self.state = SGState::Connected(State {
+                connection_state: ConnectionState::Connecting,
+                pairing_state: PairingState::NotPaired,
+                participant_id: PARTICIPANT_ID,
+                crypto: Crypto::from_private_scalar(self.key_type, &self.key, &unprotected.public_key)?
+            });
+            self.userhash.clear();
+            self.jwt.clear();
+            self.sequence_number = 0;
+            self.acks = AckTracker::new();
+            self.next_channel_id = FIRST_CHANNEL_ID;
+        }
+
+        let request = match Packet::read(data, &self.state)? {
+            Packet::ConnectRequest(_, _, request) => request,
+            other => return Err(MockError::UnexpectedPacket(other.pkt_type()))
+        };
+        if request.request_num == request.request_group_start {
+            self.userhash = request.userhash.to_str();
+        }
+        self.jwt.push_str(request.jwt.value());
+
+        // Only the last request of a group is answered
+        if request.request_num + 1 < request.request_group_end {
+            return Ok(vec![]);
+        }
+
+        let mut iv = [0u8; 16];
+        sgcrypto::random_bytes(&mut iv)?;
+        let response = Packet::ConnectResponse(
+            SimpleHeader::new(Type::ConnectResponse, 2),
+            ConnectResponseUnprotectedData {
+                iv
+            },
+            ConnectResponseProtectedData {
+                connect_request: constants::connect_result::SUCCESS,
+                pairing_state: PairingState::NotPaired.raw(),
+                participant_id: PARTICIPANT_ID
+            }
+        );
+        let response = response.raw_bytes(&self.state)?;
+        self.state.ensure_connected_mut()?.connection_state = ConnectionState::Connected;
+
+        let status = self.message(Message::ConsoleStatus(self.status.clone()), constants::channel::CORE, true)?;
+        Ok(vec![response, status])
+    }
+
+    fn handle_message(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, MockError> {
+        let (header, message) = match Packet::read(data, &self.state)? {
+            Packet::Message(header, message) => (header, message),
+            other => return Err(MockError::UnexpectedPacket(other.pkt_type()))
+        };
+
+        let is_new = self.acks.receive(header.sequence_number);
+
+        let mut replies = Vec::new();
+        if header.flags.need_ack {
+            let ack = Message::Acknowledge(self.acks.acknowledgement(header.sequence_number));
+            replies.push(self.message(ack, constants::channel::ACK, false)?);
+        }
+
+        if !is_new {
+            return Ok(replies);
+        }
+
+        match message {
+            Message::StartChannelRequest(request) => {
+                let response = Message::StartChannelResponse(StartChannelResponseData {
+                    channel_request_id: request.channel_request_id,
+                    target_channel_id: self.next_channel_id,
+                    result: 0
+                });
+                self.next_channel_id += 1;
+                replies.push(self.message(response, constants::channel::CORE, true)?);
+            },
+            Message::TitleLaunch(launch) => {
+                for title in self.status.active_titles.elements.iter_mut() {
+                    title.title_disposition.has_focus = false;
+                }
+                self.status.active_titles.elements.push(ActiveTitle {
+                    title_id: launch.launched_title_id().unwrap_or(LAUNCHED_TITLE_ID),
+                    title_disposition: TitleDisposition {
+                        has_focus: true,
+                        location: launch.location
+                    },
+                    product_id: constants::uuid::NONE.clone(),
+                    sandbox_id: constants::uuid::NONE.clone(),
+                    aum: launch.uri.clone()
+                });
+                replies.push(self.message(Message::ConsoleStatus(self.status.clone()), constants::channel::CORE, true)?);
+            },
+            Message::Disconnect(_) => {
+                self.state = SGState::Disconnected;
+            },
+            _ => {}
+        }
+
+        Ok(replies)
+    }
+
+    fn message(&mut self, message: Message, channel_id: u64, need_ack: bool) -> Result<Vec<u8>, MockError> {
+        self.sequence_number += 1;
+
+        let header = MessageHeader {
+            pkt_type: Type::Message,
+            protected_payload_length: 0,
+            sequence_number: self.sequence_number,
+            target_participant_id: PARTICIPANT_ID,
+            source_participant_id: 0,
+            flags: MessageHeaderFlags {
+                msg_type: message.msg_type(),
+                need_ack,
+                is_fragment: false,
+                version: 2
+            },
+            channel_id
+        };
+
+        Ok(Packet::Message(header, message).raw_bytes(&self.state)?)
+    }
+}
+
+/// A `MockConsole` answering on loopback, stopped when dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<MockConsole>>
+}
+
+impl MockServer {
+    /// The address to point a client at
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the server, giving back the console to inspect its state
+    pub fn stop(mut self) -> MockConsole {
+        self.stop.store(true, Ordering::SeqCst);
+        // Only panics if the thread did, in which case the test failed anyway
+        self.thread.take().unwrap().join().unwrap()
+    }
+
+    fn run(mut console: MockConsole, socket: UdpSocket, stop: &AtomicBool) -> MockConsole {
+        let mut buf = [0u8; 2048];
+
+        while !stop.load(Ordering::SeqCst) {
+            let (len, client) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(_) => continue
+            };
+
+            // A real console drops what it can't make sense of, so does the mock
+            match console.handle(&buf[..len]) {
+                Ok(replies) => {
+                    for reply in replies {
+                        let _ = socket.send_to(&reply, client);
+                    }
+                },
+                Err(err) => console.last_error = Some(err)
+            }
+        }
+
+        console
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,11 +1,18 @@
 use uuid::Uuid;
 
-use ::packet::*;
-use ::packet::simple::*;
-use ::util::{SGString, PublicKey, UUID};
+use crate::packet::*;
+use crate::packet::simple::*;
+use crate::util::{SGString, PublicKey, UUID};
 
 use std::string::String;
 
+/// The largest connect request that is sent in a single datagram
+pub const CONNECT_REQUEST_MAX_LEN: usize = 1024;
+/// The header, the unprotected data without the public key and the signature of a connect request
+const CONNECT_REQUEST_FIXED_LEN: usize = 8 + 16 + 2 + 16 + 32;
+/// The prefixes and terminators of userhash and jwt plus the request numbers
+const CONNECT_REQUEST_PROTECTED_OVERHEAD: usize = 3 + 3 + 12;
+
 pub fn power_on_request(live_id: String) -> Packet {
     let header = SimpleHeader::new(Type::PowerOnRequest, 2);
     let data = PowerOnRequestData {
@@ -44,3 +51,117 @@ pub fn connect_request(sg_uuid: Uuid, public_key: PublicKey, iv: [u8; 16], userh
 
     Packet::ConnectRequest(header, unprotected_data, protected_data)
 }
+
+/// Splits the credentials over as many connect requests as it takes for each to fit into a datagram
+///
+/// The userhash only goes into the first request, the jwt is spread over all
+/// of them in order. The requests are numbered from `request_group_start`,
+/// `request_group_end` is the number after the last one. The console only
+/// answers the last request of the group.
+///
+/// # Arguments
+/// * next_iv - gives the IV for each request
+pub fn connect_request_group<F, E>(sg_uuid: Uuid, public_key: PublicKey, userhash: String, jwt: String, request_group_start: u32, mut next_iv: F) -> Result<Vec<Packet>, E>
+    where F: FnMut() -> Result<[u8; 16], E> {
+    let chunks = jwt_chunks(&jwt, public_key.key().len(), userhash.len());
+    let request_group_end = request_group_start + chunks.len() as u32;
+
+    chunks.into_iter().enumerate()
+        .map(|(index, chunk)| {
+            let userhash = if index == 0 { userhash.clone() } else { String::new() };
+            let request_num = request_group_start + index as u32;
+            Ok(connect_request(sg_uuid, public_key.clone(), next_iv()?, userhash, chunk.to_string(), request_num, request_group_start, request_group_end))
+        })
+        .collect()
+}
+
+/// Splits the jwt on character boundaries so every connect request stays within `CONNECT_REQUEST_MAX_LEN`
+fn jwt_chunks(jwt: &str, public_key_len: usize, userhash_len: usize) -> Vec<&str> {
+    // The protected data is padded to whole blocks
+    let max_protected_len = (CONNECT_REQUEST_MAX_LEN - CONNECT_REQUEST_FIXED_LEN - public_key_len) / 16 * 16;
+    let mut max_len = max_protected_len.saturating_sub(CONNECT_REQUEST_PROTECTED_OVERHEAD + userhash_len);
+
+    let mut chunks = Vec::new();
+    let mut rest = jwt;
+    loop {
+        let mut end = max_len.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+
+        if rest.is_empty() {
+            return chunks;
+        }
+        max_len = max_protected_len - CONNECT_REQUEST_PROTECTED_OVERHEAD;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use protocol::Parcel;
+
+    use super::*;
+    use crate::util::PublicKeyType;
+
+    fn group(userhash: &str, jwt: &str) -> Vec<Packet> {
+        group_with_key(PublicKeyType::EcDhP256, userhash, jwt)
+    }
+
+    fn group_with_key(key_type: PublicKeyType, userhash: &str, jwt: &str) -> Vec<Packet> {
+        let public_key = PublicKey::new(key_type, vec![0u8; key_type.key_len()]).unwrap();
+        connect_request_group::<_, ()>(Uuid::nil(), public_key, userhash.to_string(), jwt.to_string(), 0, || Ok([0u8; 16])).unwrap()
+    }
+
+    fn protected(packet: &Packet) -> &ConnectRequestProtectedData {
+        match *packet {
+            Packet::ConnectRequest(_, _, ref protected_data) => protected_data,
+            _ => panic!("Wrong type")
+        }
+    }
+
+    #[test]
+    fn short_credentials_fit_one_request() {
+        let packets = group("deadbeefdeadbeefde", "dummy_token");
+        assert_eq!(packets.len(), 1);
+
+        let data = protected(&packets[0]);
+        assert_eq!(data.userhash.to_str(), "deadbeefdeadbeefde");
+        assert_eq!(data.jwt.to_str(), "dummy_token");
+        assert_eq!((data.request_num, data.request_group_start, data.request_group_end), (0, 0, 1));
+    }
+
+    #[test]
+    fn long_jwts_are_split() {
+        let jwt = "eyJhbGciOiJSUzI1NiJ9.".repeat(150);
+        let packets = group("deadbeefdeadbeefde", &jwt);
+        assert_eq!(packets.len(), 4);
+
+        let mut reassembled = String::new();
+        for (index, packet) in packets.iter().enumerate() {
+            let data = protected(packet);
+            assert_eq!(data.request_num, index as u32);
+            assert_eq!(data.request_group_start, 0);
+            assert_eq!(data.request_group_end, 4);
+            assert_eq!(data.userhash.to_str().is_empty(), index != 0);
+
+            let protected_len = data.raw_bytes().unwrap().len();
+            assert!(CONNECT_REQUEST_FIXED_LEN + 64 + protected_len <= CONNECT_REQUEST_MAX_LEN);
+            reassembled.push_str(data.jwt.value());
+        }
+        assert_eq!(reassembled, jwt);
+    }
+
+    #[test]
+    fn larger_keys_leave_less_room() {
+        let jwt = "eyJhbGciOiJSUzI1NiJ9.".repeat(150);
+        let packets = group_with_key(PublicKeyType::EcDhP521, "deadbeefdeadbeefde", &jwt);
+        assert_eq!(packets.len(), 4);
+
+        for packet in packets.iter() {
+            let protected_len = protected(packet).raw_bytes().unwrap().len();
+            assert!(CONNECT_REQUEST_FIXED_LEN + 132 + protected_len <= CONNECT_REQUEST_MAX_LEN);
+        }
+    }
+}
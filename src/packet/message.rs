@@ -1,13 +1,75 @@
 use std::io::{Read, Write};
+use std::time::Duration;
 
-use ::packet::{Type, Header};
-use ::util::{SGString, UUID};
+use crate::packet::{Type, Header};
+use crate::util::{self, SGString, UUID};
 
 use protocol;
 use protocol::{Parcel, DynArray};
 use bit_field::BitField;
 use num_traits::FromPrimitive;
 
+/// Implements `Parcel` for a `Primitive` enum, failing on values it has no variant for
+macro_rules! primitive_parcel {
+    ($name:ident, $repr:ty, $from:ident) => {
+        impl Parcel for $name {
+            fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+                $name::$from(<$repr>::read(read)?)
+                    .ok_or_else(|| protocol::Error::from_kind(protocol::ErrorKind::UnknownPacketId))
+            }
+
+            fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+                (*self as $repr).write(write)?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Declares an enum for a value the console reports, keeping values this crate
+/// doesn't know as `Unknown` instead of failing the whole message
+macro_rules! reported_enum {
+    ($(#[$meta:meta])* pub enum $name:ident: $repr:ty { $($variant:ident = $value:literal),* }) => {
+        $(#[$meta])*
+        #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+        pub enum $name {
+            $($variant,)*
+            /// A value this crate doesn't know about
+            Unknown($repr)
+        }
+
+        impl $name {
+            pub fn from_raw(value: $repr) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    other => $name::Unknown(other)
+                }
+            }
+
+            /// The value as it is sent on the wire
+            pub fn raw(&self) -> $repr {
+                match *self {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => value
+                }
+            }
+        }
+
+        impl Parcel for $name {
+            fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+                Ok($name::from_raw(<$repr>::read(read)?))
+            }
+
+            fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+                self.raw().write(write)?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
 #[repr(u16)]
 #[derive(Primitive, PartialEq, Eq, Copy, Clone, Debug)]
 pub enum MessageType {
@@ -108,7 +170,247 @@ pub enum Message {
     SystemTextInput(SystemTextInputData),
     SystemTouch(TouchData),
     SystemTextAcknowledge(SystemTextAcknowledgeData),
-    SystemTextDone(SystemTextDoneData)
+    SystemTextDone(SystemTextDoneData),
+    /// A piece of a message of the given type that was too large for a single packet
+    Fragment(MessageType, FragmentData)
+}
+
+impl Message {
+    /// Returns the type that goes into the header flags for this message
+    pub fn msg_type(&self) -> MessageType {
+        match *self {
+            Message::Null => MessageType::Null,
+            Message::Acknowledge(_) => MessageType::Acknowledge,
+            Message::Group => MessageType::Group,
+            Message::LocalJoin(_) => MessageType::LocalJoin,
+            Message::StopActivity => MessageType::StopActivity,
+            Message::AuxiliaryStream(_) => MessageType::AuxiliaryStream,
+            Message::ActiveSurfaceChange(_) => MessageType::ActiveSurfaceChange,
+            Message::Navigate => MessageType::Navigate,
+            Message::Json(_) => MessageType::Json,
+            Message::Tunnel => MessageType::Tunnel,
+            Message::ConsoleStatus(_) => MessageType::ConsoleStatus,
+            Message::TitleTextConfiguration(_) => MessageType::TitleTextConfiguration,
+            Message::TitleTextInput(_) => MessageType::TitleTextInput,
+            Message::TitleTextSelection(_) => MessageType::TitleTextSelection,
+            Message::MirroringRequest => MessageType::MirroringRequest,
+            Message::TitleLaunch(_) => MessageType::TitleLaunch,
+            Message::StartChannelRequest(_) => MessageType::StartChannelRequest,
+            Message::StartChannelResponse(_) => MessageType::StartChannelResponse,
+            Message::StopChannel(_) => MessageType::StopChannel,
+            Message::System => MessageType::System,
+            Message::Disconnect(_) => MessageType::Disconnect,
+            Message::TitleTouch(_) => MessageType::TitleTouch,
+            Message::Accelerometer(_) => MessageType::Accelerometer,
+            Message::Gyrometer(_) => MessageType::Gyrometer,
+            Message::Inclinometer(_) => MessageType::Inclinometer,
+            Message::Compass(_) => MessageType::Compass,
+            Message::Orientation(_) => MessageType::Orientation,
+            Message::PairedIdentityStateChanged(_) => MessageType::PairedIdentityStateChanged,
+            Message::Unsnap(_) => MessageType::Unsnap,
+            Message::GameDvrRecord(_) => MessageType::GameDvrRecord,
+            Message::PowerOff(_) => MessageType::PowerOff,
+            Message::MediaControllerRemoved(_) => MessageType::MediaControllerRemoved,
+            Message::MediaCommand(_) => MessageType::MediaCommand,
+            Message::MediaCommandResult(_) => MessageType::MediaCommandResult,
+            Message::MediaState(_) => MessageType::MediaState,
+            Message::Gamepad(_) => MessageType::Gamepad,
+            Message::SystemTextConfiguration(_) => MessageType::SystemTextConfiguration,
+            Message::SystemTextInput(_) => MessageType::SystemTextInput,
+            Message::SystemTouch(_) => MessageType::SystemTouch,
+            Message::SystemTextAcknowledge(_) => MessageType::SystemTextAcknowledge,
+            Message::SystemTextDone(_) => MessageType::SystemTextDone,
+            Message::Fragment(msg_type, _) => msg_type
+        }
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        match *self {
+            Message::Fragment(..) => true,
+            _ => false
+        }
+    }
+
+    /// Decodes the payload of a message of the given type
+    ///
+    /// Types we don't know the layout of yet decode to `Message::Null`.
+    pub fn read_payload(msg_type: MessageType, data: &[u8]) -> Result<Message, protocol::Error> {
+        let message = match msg_type {
+            MessageType::Acknowledge => {
+                Message::Acknowledge(
+                    AcknowledgeData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::LocalJoin => {
+                Message::LocalJoin(
+                    LocalJoinData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::AuxiliaryStream => {
+                Message::AuxiliaryStream(
+                    AuxiliaryStreamData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::ActiveSurfaceChange => {
+                Message::ActiveSurfaceChange(
+                    ActiveSurfaceChangeData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Json => {
+                Message::Json(
+                        JsonData::from_raw_bytes(data)?
+                    )
+            },
+            MessageType::ConsoleStatus => {
+                Message::ConsoleStatus(
+                    ConsoleStatusData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::TitleTextConfiguration => {
+                Message::TitleTextConfiguration(
+                    TextConfigurationData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::TitleTextInput => {
+                Message::TitleTextInput(
+                    TitleTextInputData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::TitleTextSelection => {
+                Message::TitleTextSelection(
+                    TitleTextSelectionData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::TitleLaunch => {
+                Message::TitleLaunch(
+                    TitleLaunchData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::StartChannelRequest => {
+                Message::StartChannelRequest(
+                    StartChannelRequestData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::StartChannelResponse => {
+                Message::StartChannelResponse(
+                    StartChannelResponseData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::StopChannel => {
+                Message::StopChannel(
+                    StopChannelData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Disconnect => {
+                Message::Disconnect(
+                    DisconnectData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::TitleTouch => {
+                Message::TitleTouch(
+                    TouchData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Accelerometer => {
+                Message::Accelerometer(
+                    AccelerometerData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Gyrometer => {
+                Message::Gyrometer(
+                    GyrometerData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Inclinometer => {
+                Message::Inclinometer(
+                    InclinometerData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Compass => {
+                Message::Compass(
+                    CompassData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Orientation => {
+                Message::Orientation(
+                    OrientationData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::PairedIdentityStateChanged => {
+                Message::PairedIdentityStateChanged(
+                    PairedIdentityStateChangedData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Unsnap => {
+                Message::Unsnap(
+                    UnsnapData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::GameDvrRecord => {
+                Message::GameDvrRecord(
+                    GameDvrRecordData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::PowerOff => {
+                Message::PowerOff(
+                    PowerOffData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::MediaControllerRemoved => {
+                Message::MediaControllerRemoved(
+                    MediaControllerRemovedData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::MediaCommand => {
+                Message::MediaCommand(
+                    MediaCommandData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::MediaCommandResult => {
+                Message::MediaCommandResult(
+                    MediaCommandResultData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::MediaState => {
+                Message::MediaState(
+                    MediaStateData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::Gamepad => {
+                Message::Gamepad(
+                    GamepadData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::SystemTextConfiguration => {
+                Message::SystemTextConfiguration(
+                    TextConfigurationData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::SystemTextInput => {
+                Message::SystemTextInput(
+                    SystemTextInputData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::SystemTouch => {
+                Message::SystemTouch(
+                    TouchData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::SystemTextAcknowledge => {
+                Message::SystemTextAcknowledge(
+                    SystemTextAcknowledgeData::from_raw_bytes(data)?
+                )
+            },
+            MessageType::SystemTextDone => {
+                Message::SystemTextDone(
+                    SystemTextDoneData::from_raw_bytes(data)?
+                )
+            }
+            _ => Message::Null
+        };
+
+        Ok(message)
+    }
 }
 
 impl Parcel for Message {
@@ -152,6 +454,7 @@ impl Parcel for Message {
                 Message::SystemTextAcknowledge(ref data) => data.write(write),
                 Message::SystemTextDone(ref data) => data.write(write),
                 Message::Json(ref data) => data.write(write),
+                Message::Fragment(_, ref data) => data.write(write),
 
                 _ => Err(protocol::Error::from_kind(protocol::ErrorKind::UnknownPacketId))
 
@@ -194,7 +497,7 @@ impl Parcel for MessageHeaderFlags {
         Ok(MessageHeaderFlags {
             msg_type: MessageType::from_u16(flags.get_bits(0..12)).unwrap(),  // todo: enumify
             need_ack: flags.get_bit(13),
-            is_fragment: flags.get_bit(14),
+            is_fragment: flags.get_bit(12),
             version: flags.get_bits(14..16)
         })
     }
@@ -204,7 +507,7 @@ impl Parcel for MessageHeaderFlags {
 
         data.set_bits(0..12, (self.msg_type as u16).get_bits(0..12));
         data.set_bit(13, self.need_ack);
-        data.set_bit(14, self.is_fragment);
+        data.set_bit(12, self.is_fragment);
         data.set_bits(14..16, self.version.get_bits(0..2));
 
         data.write(write)?;
@@ -345,13 +648,46 @@ pub struct ConsoleStatusData {
     pub active_titles: DynArray<u16, ActiveTitle>
 }
 
-define_composite_type!(ActiveTitle {
-    title_id: u32,
-    title_disposition: u16,
-    product_id: UUID<u8>,
-    sandbox_id: UUID<u8>,
-    aum: SGString
-});
+#[derive(Protocol, Clone, Debug, PartialEq)]
+pub struct ActiveTitle {
+    pub title_id: u32,
+    pub title_disposition: TitleDisposition,
+    pub product_id: UUID<u8>,
+    pub sandbox_id: UUID<u8>,
+    pub aum: SGString
+}
+
+/// Where a title is shown and whether it has focus
+///
+/// On the wire the location takes the low 15 bits and focus the top bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TitleDisposition {
+    pub has_focus: bool,
+    pub location: TitleLocation
+}
+
+impl Parcel for TitleDisposition {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        let disposition = u16::read(read)?;
+        let location = TitleLocation::from_raw(disposition.get_bits(0..15));
+
+        Ok(TitleDisposition {
+            has_focus: disposition.get_bit(15),
+            location
+        })
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        let mut data = 0 as u16;
+
+        data.set_bits(0..15, self.location.raw());
+        data.set_bit(15, self.has_focus);
+
+        data.write(write)?;
+
+        Ok(())
+    }
+}
 
 // text_configuration = 'text_configuration' / Struct(
 //     'text_session_id' / Int64ub,
@@ -381,11 +717,19 @@ pub struct TextConfigurationData {
 //     'text' / SGString('utf8')
 // ) / StructObj
 
+reported_enum! {
+    /// How a text session ended
+    pub enum TextResult: u16 {
+        Cancel = 0,
+        Accept = 1
+    }
+}
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct TitleTextInputData {
     pub session_id: u64,
     pub buffer_version: u32,
-    pub result: u16,
+    pub result: TextResult,
     pub text: SGString
 }
 
@@ -409,12 +753,58 @@ pub struct TitleTextSelectionData {
 //     'uri' / SGString()
 // ) / StructObj
 
+reported_enum! {
+    /// Where on screen a launched title shows up
+    pub enum TitleLocation: u16 {
+        Full = 0,
+        Fill = 1,
+        Snapped = 2,
+        StartView = 3,
+        System = 4,
+        Default = 5
+    }
+}
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct TitleLaunchData {
-    pub location: u16,
+    pub location: TitleLocation,
     pub uri: SGString
 }
 
+impl TitleLaunchData {
+    pub fn new(uri: String, location: TitleLocation) -> Self {
+        TitleLaunchData {
+            location,
+            uri: SGString::from_str(uri)
+        }
+    }
+
+    /// Launches the title with the given title id
+    pub fn title_id(title_id: u32, location: TitleLocation) -> Self {
+        TitleLaunchData::new(format!("ms-xbl-{:08X}://default/", title_id), location)
+    }
+
+    /// Launches an app by its application user model id, like `Microsoft.MicrosoftEdge_8wekyb3d8bbwe!MicrosoftEdge`
+    pub fn aum(aum: &str, location: TitleLocation) -> Self {
+        TitleLaunchData::new(format!("appx:{}", aum), location)
+    }
+
+    /// Opens the store page of the product with the given store id, like `9WZDNCRFJ3TJ`
+    pub fn store_product(product_id: &str, location: TitleLocation) -> Self {
+        TitleLaunchData::new(format!("ms-windows-store://pdp/?productid={}", product_id), location)
+    }
+
+    /// The title id of a launch made with `TitleLaunchData::title_id`, `None` for other URIs
+    pub fn launched_title_id(&self) -> Option<u32> {
+        let uri = self.uri.value();
+        if !uri.starts_with("ms-xbl-") {
+            return None;
+        }
+        let end = uri.find("://")?;
+        u32::from_str_radix(&uri["ms-xbl-".len()..end], 16).ok()
+    }
+}
+
 // start_channel_request = 'start_channel_request' / Struct(
 //     'channel_request_id' / Int32ub,
 //     'title_id' / Int32ub,
@@ -473,21 +863,34 @@ pub struct DisconnectData {
 
 // touch = 'touch' / Struct(
 //     'touch_msg_timestamp' / Int32ub,
-//     'active_titles' / PrefixedArray(Int16ub, _touchpoint)
+//     'touchpoints' / PrefixedArray(Int16ub, _touchpoint)
 // ) / StructObj
 
+#[repr(u16)]
+#[derive(Primitive, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TouchAction {
+    Down = 1,
+    Move = 2,
+    Up = 3,
+    Cancel = 4
+}
+
+primitive_parcel!(TouchAction, u16, from_u16);
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct TouchData {
     pub timestamp: u32,
-    pub active_titles: DynArray<u16, Touchpoint>
+    pub touchpoints: DynArray<u16, Touchpoint>
 }
 
-define_composite_type!(Touchpoint {
-    id: u32,
-    action: u16,
-    x: u32,
-    y: u32
-});
+/// A single finger on the touch surface, `id` stays the same while it moves
+#[derive(Protocol, Clone, Debug, PartialEq)]
+pub struct Touchpoint {
+    pub id: u32,
+    pub action: TouchAction,
+    pub x: u32,
+    pub y: u32
+}
 
 // accelerometer = 'accelerometer' / Struct(
 //     'timestamp' / Int64ub,
@@ -570,9 +973,16 @@ pub struct OrientationData {
 //     'state' / Int16ub
 // ) / StructObj
 
+reported_enum! {
+    pub enum PairingState: u16 {
+        NotPaired = 0x0,
+        Paired = 0x1
+    }
+}
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct PairedIdentityStateChangedData {
-    pub state: u16  // todo: enumify
+    pub state: PairingState
 }
 
 // unsnap = 'unsnap' / Struct(
@@ -620,12 +1030,65 @@ pub struct MediaControllerRemovedData {
 //     'seek_position' / If(this.command == MediaControlCommand.Seek, Int64ub)
 // ) / StructObj
 
-#[derive(Protocol, Clone, Debug, PartialEq)]
+#[repr(u32)]
+#[derive(Primitive, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum MediaControlCommand {
+    Play = 0x2,
+    Pause = 0x4,
+    PlayPauseToggle = 0x8,
+    Stop = 0x10,
+    Record = 0x20,
+    NextTrack = 0x40,
+    PrevTrack = 0x80,
+    FastForward = 0x100,
+    Rewind = 0x200,
+    ChannelUp = 0x400,
+    ChannelDown = 0x800,
+    Back = 0x1000,
+    View = 0x2000,
+    Menu = 0x4000,
+    Seek = 0x8000
+}
+
+primitive_parcel!(MediaControlCommand, u32, from_u32);
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct MediaCommandData {
     pub request_id: u64,
     pub title_id: u32,
-    pub command: u32  // todo: enumify
-    // todo add seek_position
+    pub command: MediaControlCommand,
+    /// Where to seek to in 100ns ticks, only present for `MediaControlCommand::Seek`
+    pub seek_position: Option<u64>
+}
+
+impl Parcel for MediaCommandData {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        let request_id = u64::read(read)?;
+        let title_id = u32::read(read)?;
+        let command = MediaControlCommand::read(read)?;
+        let seek_position = match command {
+            MediaControlCommand::Seek => Some(u64::read(read)?),
+            _ => None
+        };
+
+        Ok(MediaCommandData {
+            request_id,
+            title_id,
+            command,
+            seek_position
+        })
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        self.request_id.write(write)?;
+        self.title_id.write(write)?;
+        self.command.write(write)?;
+        if self.command == MediaControlCommand::Seek {
+            self.seek_position.unwrap_or(0).write(write)?;
+        }
+
+        Ok(())
+    }
 }
 
 // media_command_result = 'media_command_result' / Struct(
@@ -659,28 +1122,195 @@ pub struct MediaCommandResultData {
 //     ))
 // ) / StructObj
 
+reported_enum! {
+    pub enum MediaType: u16 {
+        NoMedia = 0,
+        Music = 1,
+        Video = 2,
+        Image = 3,
+        Conversation = 4,
+        Game = 5
+    }
+}
+
+reported_enum! {
+    pub enum PlaybackStatus: u16 {
+        Closed = 0,
+        Changing = 1,
+        Stopped = 2,
+        Playing = 3,
+        Paused = 4
+    }
+}
+
+reported_enum! {
+    pub enum SoundLevel: u16 {
+        Muted = 0,
+        Low = 1,
+        Full = 2
+    }
+}
+
+bitflags! {
+    /// The media commands the title currently accepts, see `MediaControlCommand`
+    pub struct EnabledCommands: u32 {
+        const PLAY = 0x2;
+        const PAUSE = 0x4;
+        const PLAY_PAUSE_TOGGLE = 0x8;
+        const STOP = 0x10;
+        const RECORD = 0x20;
+        const NEXT_TRACK = 0x40;
+        const PREV_TRACK = 0x80;
+        const FAST_FORWARD = 0x100;
+        const REWIND = 0x200;
+        const CHANNEL_UP = 0x400;
+        const CHANNEL_DOWN = 0x800;
+        const BACK = 0x1000;
+        const VIEW = 0x2000;
+        const MENU = 0x4000;
+        const SEEK = 0x8000;
+    }
+}
+
+impl EnabledCommands {
+    pub fn allows(&self, command: MediaControlCommand) -> bool {
+        self.contains(EnabledCommands::from_bits_truncate(command as u32))
+    }
+}
+
+impl Parcel for EnabledCommands {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        Ok(EnabledCommands::from_bits_truncate(u32::read(read)?))
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        self.bits().write(write)?;
+
+        Ok(())
+    }
+}
+
+/// Metadata about the playing media, like its title or artist
+///
+/// Entries keep the order the console sent them in.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct MediaMetadata {
+    entries: Vec<(String, String)>
+}
+
+impl MediaMetadata {
+    pub fn new() -> Self {
+        MediaMetadata {
+            entries: Vec::new()
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|entry| entry.0 == name)
+            .map(|entry| entry.1.as_str())
+    }
+
+    /// Sets `name` to `value`, replacing an existing entry in place
+    pub fn insert(&mut self, name: String, value: String) {
+        match self.entries.iter_mut().find(|entry| entry.0 == name) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((name, value))
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|entry| (entry.0.as_str(), entry.1.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.get("title")
+    }
+
+    pub fn subtitle(&self) -> Option<&str> {
+        self.get("subtitle")
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.get("artist")
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.get("album")
+    }
+}
+
+impl Parcel for MediaMetadata {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        let count = u16::read(read)?;
+
+        let mut metadata = MediaMetadata::new();
+        for _ in 0..count {
+            let name = SGString::read(read)?;
+            let value = SGString::read(read)?;
+            metadata.entries.push((name.to_str(), value.to_str()));
+        }
+
+        Ok(metadata)
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        (self.entries.len() as u16).write(write)?;
+        for &(ref name, ref value) in self.entries.iter() {
+            SGString::from_str(name.clone()).write(write)?;
+            SGString::from_str(value.clone()).write(write)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct MediaStateData {
     pub title_id: u32,
     pub aum_id: SGString,
     pub asset_id: SGString,
-    pub media_type: u16,
-    pub sound_level: u16,
-    pub enabled_commands: u32,
-    pub playback_status: u16,
+    pub media_type: MediaType,
+    pub sound_level: SoundLevel,
+    pub enabled_commands: EnabledCommands,
+    pub playback_status: PlaybackStatus,
     pub rate: f32,
     pub position: u64,
     pub media_start: u64,
     pub media_end: u64,
     pub min_seek: u64,
     pub max_seek: u64,
-    pub metadata: DynArray<u16, MediaStateMetadata>
+    pub metadata: MediaMetadata
 }
 
-define_composite_type!(MediaStateMetadata {
-    name: SGString,
-    value: SGString
-});
+impl MediaStateData {
+    /// How far into the media playback is
+    pub fn playback_position(&self) -> Duration {
+        util::ticks_to_duration(self.position.saturating_sub(self.media_start))
+    }
+
+    /// The length of the media, zero if the title doesn't know it
+    pub fn duration(&self) -> Duration {
+        util::ticks_to_duration(self.media_end.saturating_sub(self.media_start))
+    }
+
+    /// The range `MediaControlCommand::Seek` accepts positions in
+    pub fn seek_range(&self) -> (Duration, Duration) {
+        (util::ticks_to_duration(self.min_seek), util::ticks_to_duration(self.max_seek))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback_status == PlaybackStatus::Playing
+    }
+}
 
 // gamepad = 'gamepad' / Struct(
 //     'timestamp' / Int64ub,
@@ -693,10 +1323,44 @@ define_composite_type!(MediaStateMetadata {
 //     'right_thumbstick_y' / Float32b
 // ) / StructObj
 
+bitflags! {
+    /// The buttons held down in a gamepad report
+    pub struct GamepadButtons: u16 {
+        const ENROLL = 0x1;
+        const NEXUS = 0x2;
+        const MENU = 0x4;
+        const VIEW = 0x8;
+        const A = 0x10;
+        const B = 0x20;
+        const X = 0x40;
+        const Y = 0x80;
+        const DPAD_UP = 0x100;
+        const DPAD_DOWN = 0x200;
+        const DPAD_LEFT = 0x400;
+        const DPAD_RIGHT = 0x800;
+        const LEFT_SHOULDER = 0x1000;
+        const RIGHT_SHOULDER = 0x2000;
+        const LEFT_THUMBSTICK = 0x4000;
+        const RIGHT_THUMBSTICK = 0x8000;
+    }
+}
+
+impl Parcel for GamepadButtons {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        Ok(GamepadButtons::from_bits_truncate(u16::read(read)?))
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        self.bits().write(write)?;
+
+        Ok(())
+    }
+}
+
 #[derive(Protocol, Clone, Debug, PartialEq)]
 pub struct GamepadData {
     pub timestamp: u64,
-    pub buttons: u16,  // todo: bitfield or something
+    pub buttons: GamepadButtons,
     pub left_trigger: f32,
     pub right_trigger: f32,
     pub left_thumbstick_x: f32,
@@ -705,6 +1369,53 @@ pub struct GamepadData {
     pub right_thumbstick_y: f32
 }
 
+impl GamepadData {
+    /// A report with no buttons held, triggers released and thumbsticks centered
+    pub fn new() -> Self {
+        GamepadData {
+            timestamp: 0,
+            buttons: GamepadButtons::empty(),
+            left_trigger: 0.0,
+            right_trigger: 0.0,
+            left_thumbstick_x: 0.0,
+            left_thumbstick_y: 0.0,
+            right_thumbstick_x: 0.0,
+            right_thumbstick_y: 0.0
+        }
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn buttons(mut self, buttons: GamepadButtons) -> Self {
+        self.buttons = buttons;
+        self
+    }
+
+    /// Sets how far the triggers are pulled, from 0.0 to 1.0
+    pub fn triggers(mut self, left: f32, right: f32) -> Self {
+        self.left_trigger = left;
+        self.right_trigger = right;
+        self
+    }
+
+    /// Sets the left thumbstick position, both axes from -1.0 to 1.0
+    pub fn left_thumbstick(mut self, x: f32, y: f32) -> Self {
+        self.left_thumbstick_x = x;
+        self.left_thumbstick_y = y;
+        self
+    }
+
+    /// Sets the right thumbstick position, both axes from -1.0 to 1.0
+    pub fn right_thumbstick(mut self, x: f32, y: f32) -> Self {
+        self.right_thumbstick_x = x;
+        self.right_thumbstick_y = y;
+        self
+    }
+}
+
 // system_text_input = 'system_text_input' / Struct(
 //     'text_session_id' / Int32ub,
 //     'base_version' / Int32ub,
@@ -714,11 +1425,15 @@ pub struct GamepadData {
 //     'selection_length' / Int32ub,
 //     'flags' / Int16ub,
 //     'text_chunk_byte_start' / Int32ub,
-//     'delta' / Pass,  # TODO: some variable copying magic, has a Int16ub length field
-//     'text_chunk' / SGString('utf8')
+//     'text_chunk' / SGString('utf8'),
+//     'delta' / Optional(PrefixedArray(Int16ub, Struct(
+//         'offset' / Int32ub,
+//         'delete_count' / Int32ub,
+//         'insert_content' / SGString('utf8')
+//     )))
 // ) / StructObj
 
-#[derive(Protocol, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SystemTextInputData {
     pub session_id: u32,
     pub base_version: u32,
@@ -728,7 +1443,115 @@ pub struct SystemTextInputData {
     pub selection_end: u32,
     pub flags: u16,
     pub text_chunk_byte_start: u32,
-    pub text_chunk: SGString
+    pub text_chunk: SGString,
+    /// Edits turning the text at `base_version` into the submitted one, the
+    /// console leaves this out entirely when it sends the whole text
+    pub delta: Option<Vec<TextDelta>>
+}
+
+impl SystemTextInputData {
+    /// Applies the delta of this message to `text`, see `TextDelta::apply_all`
+    ///
+    /// Returns false if there is no delta.
+    pub fn apply_delta(&self, text: &mut String) -> bool {
+        match self.delta {
+            Some(ref delta) => TextDelta::apply_all(delta, text),
+            None => false
+        }
+    }
+}
+
+impl Parcel for SystemTextInputData {
+    fn read(read: &mut Read) -> Result<Self, protocol::Error> {
+        let session_id = u32::read(read)?;
+        let base_version = u32::read(read)?;
+        let submitted_version = u32::read(read)?;
+        let total_text_byte_len = u32::read(read)?;
+        let selection_start = u32::read(read)?;
+        let selection_end = u32::read(read)?;
+        let flags = u16::read(read)?;
+        let text_chunk_byte_start = u32::read(read)?;
+        let text_chunk = SGString::read(read)?;
+
+        // The delta is only there if the message goes on after the text
+        let mut count = [0u8; 2];
+        let delta = match read.read(&mut count[..1])? {
+            0 => None,
+            _ => {
+                read.read_exact(&mut count[1..])?;
+
+                let mut delta = Vec::new();
+                for _ in 0..u16::from_be_bytes(count) {
+                    delta.push(TextDelta::read(read)?);
+                }
+                Some(delta)
+            }
+        };
+
+        Ok(SystemTextInputData {
+            session_id,
+            base_version,
+            submitted_version,
+            total_text_byte_len,
+            selection_start,
+            selection_end,
+            flags,
+            text_chunk_byte_start,
+            text_chunk,
+            delta
+        })
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), protocol::Error> {
+        self.session_id.write(write)?;
+        self.base_version.write(write)?;
+        self.submitted_version.write(write)?;
+        self.total_text_byte_len.write(write)?;
+        self.selection_start.write(write)?;
+        self.selection_end.write(write)?;
+        self.flags.write(write)?;
+        self.text_chunk_byte_start.write(write)?;
+        self.text_chunk.write(write)?;
+
+        if let Some(ref delta) = self.delta {
+            (delta.len() as u16).write(write)?;
+            for edit in delta.iter() {
+                edit.write(write)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single edit of a text, byte offsets refer to the UTF-8 encoded text
+#[derive(Protocol, Clone, Debug, PartialEq)]
+pub struct TextDelta {
+    pub offset: u32,
+    pub delete_count: u32,
+    pub insert_content: SGString
+}
+
+impl TextDelta {
+    /// Applies the edits in order to `text`
+    ///
+    /// Returns false and leaves `text` alone if an edit reaches past the end
+    /// of the text or splits up a character.
+    pub fn apply_all(delta: &[TextDelta], text: &mut String) -> bool {
+        let mut edited = text.clone();
+
+        for edit in delta.iter() {
+            let start = edit.offset as usize;
+            let end = start + edit.delete_count as usize;
+            if end > edited.len() || !edited.is_char_boundary(start) || !edited.is_char_boundary(end) {
+                return false;
+            }
+            edited.replace_range(start..end, edit.insert_content.value());
+        }
+
+        *text = edited;
+        true
+    }
 }
 
 // system_text_acknowledge = 'system_text_acknowledge' / Struct(
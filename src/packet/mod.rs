@@ -5,11 +5,11 @@ pub mod factory;
 use std::io;
 use std::io::{Read, Write, Cursor};
 
-use ::state::*;
-use ::sgcrypto;
-use ::sgcrypto::Crypto;
-use ::packet::simple::*;
-use ::packet::message::*;
+use crate::state::*;
+use crate::sgcrypto;
+use crate::sgcrypto::Crypto;
+use crate::packet::simple::*;
+use crate::packet::message::*;
 
 use protocol;
 use protocol::{Parcel};
@@ -25,6 +25,9 @@ quick_error! {
         Read(err: protocol::Error) { from() }
         Signature(err: sgcrypto::Error) { }
         State(err: InvalidState) { from() }
+        TooShort(len: usize) {
+            display("A packet of {} bytes is too short to be signed", len)
+        }
         Type(pkt_type: Type) { }
     }
 }
@@ -92,6 +95,17 @@ trait Header {
 }
 
 impl Packet {
+    pub fn pkt_type(&self) -> Type {
+        match *self {
+            Packet::PowerOnRequest(ref header, _) => header.pkt_type,
+            Packet::DiscoveryRequest(ref header, _) => header.pkt_type,
+            Packet::DiscoveryResponse(ref header, _) => header.pkt_type,
+            Packet::ConnectRequest(ref header, _, _) => header.pkt_type,
+            Packet::ConnectResponse(ref header, _, _) => header.pkt_type,
+            Packet::Message(ref header, _) => header.pkt_type
+        }
+    }
+
     pub fn read(input: &[u8], state: &SGState) -> Result<Self, ReadError> {
         let mut reader = Cursor::new(input);
         let pkt_type = Type::read(&mut reader)?;
@@ -100,26 +114,33 @@ impl Packet {
         match pkt_type {
             Type::PowerOnRequest |
             Type::DiscoveryRequest |
-            Type::DiscoveryResponse |
-            Type::ConnectRequest => {
+            Type::DiscoveryResponse => {
                 state.ensure_disconnected()?;
                 Packet::read_simple(&mut reader, &state)
             }
+            // Reading a connect request takes the console's side of the shared secret
+            Type::ConnectRequest |
             Type::ConnectResponse => {
-                let internal_state = state.ensure_connected()?;
-                let data_len = input.len() - 32;
-                internal_state.crypto.verify(&input[..data_len], &input[data_len..]).map_err(ReadError::Signature)?;
+                Packet::verify(input, &state.ensure_connected()?.crypto)?;
                 Packet::read_simple(&mut reader, &state)
             }
             Type::Message => {
-                let internal_state = state.ensure_connected()?;
-                let data_len = input.len() - 32;
-                internal_state.crypto.verify(&input[..data_len], &input[data_len..]).map_err(ReadError::Signature)?;
+                Packet::verify(input, &state.ensure_connected()?.crypto)?;
                 Packet::read_message(&mut reader, &state)
             }
         }
     }
 
+    /// Checks the signature at the end of a protected packet
+    fn verify(input: &[u8], crypto: &Crypto) -> Result<(), ReadError> {
+        if input.len() < 32 {
+            return Err(ReadError::TooShort(input.len()));
+        }
+
+        let data_len = input.len() - 32;
+        crypto.verify(&input[..data_len], &input[data_len..]).map_err(ReadError::Signature)
+    }
+
     fn read_simple(reader: &mut Read, state: &SGState) -> Result<Self, ReadError> {
         let header = SimpleHeader::read(reader)?;
         match header.pkt_type {
@@ -142,7 +163,17 @@ impl Packet {
                 ))
             },
             Type::ConnectRequest => {
-                Err(ReadError::NotImplimented)
+                let internal_state = state.ensure_connected()?;
+                let unprotected = ConnectRequestUnprotectedData::read(reader)?;
+                let protected_len = header.protected_payload_length as usize;
+                let decrypted_buf = Packet::decrypt(reader, &internal_state.crypto, protected_len, &unprotected.iv)?;
+                let protected = ConnectRequestProtectedData::from_raw_bytes(&decrypted_buf)?;
+
+                Ok(Packet::ConnectRequest(
+                    header,
+                    unprotected,
+                    protected
+                ))
             },
             Type::ConnectResponse => {
                 let internal_state = state.ensure_connected()?;
@@ -172,179 +203,13 @@ impl Packet {
         internal_state.crypto.generate_iv(&header_buf[..16], &mut iv).map_err(ReadError::Decrypt)?;
         let decrypted_buf = Packet::decrypt(reader, &internal_state.crypto, header.protected_payload_length as usize, &iv)?;
 
-        // // todo: implement
-        let message = match header.flags.msg_type {
-            MessageType::Acknowledge => {
-                Message::Acknowledge(
-                    AcknowledgeData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::LocalJoin => {
-                Message::LocalJoin(
-                    LocalJoinData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::AuxiliaryStream => {
-                Message::AuxiliaryStream(
-                    AuxiliaryStreamData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::ActiveSurfaceChange => {
-                Message::ActiveSurfaceChange(
-                    ActiveSurfaceChangeData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Json => {
-                Message::Json(
-                        JsonData::from_raw_bytes(&decrypted_buf)?
-                    )
-            },
-            MessageType::ConsoleStatus => {
-                Message::ConsoleStatus(
-                    ConsoleStatusData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::TitleTextConfiguration => {
-                Message::TitleTextConfiguration(
-                    TextConfigurationData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::TitleTextInput => {
-                Message::TitleTextInput(
-                    TitleTextInputData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::TitleTextSelection => {
-                Message::TitleTextSelection(
-                    TitleTextSelectionData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::TitleLaunch => {
-                Message::TitleLaunch(
-                    TitleLaunchData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::StartChannelRequest => {
-                Message::StartChannelRequest(
-                    StartChannelRequestData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::StartChannelResponse => {
-                Message::StartChannelResponse(
-                    StartChannelResponseData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::StopChannel => {
-                Message::StopChannel(
-                    StopChannelData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Disconnect => {
-                Message::Disconnect(
-                    DisconnectData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::TitleTouch => {
-                Message::TitleTouch(
-                    TouchData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Accelerometer => {
-                Message::Accelerometer(
-                    AccelerometerData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Gyrometer => {
-                Message::Gyrometer(
-                    GyrometerData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Inclinometer => {
-                Message::Inclinometer(
-                    InclinometerData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Compass => {
-                Message::Compass(
-                    CompassData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Orientation => {
-                Message::Orientation(
-                    OrientationData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::PairedIdentityStateChanged => {
-                Message::PairedIdentityStateChanged(
-                    PairedIdentityStateChangedData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Unsnap => {
-                Message::Unsnap(
-                    UnsnapData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::GameDvrRecord => {
-                Message::GameDvrRecord(
-                    GameDvrRecordData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::PowerOff => {
-                Message::PowerOff(
-                    PowerOffData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::MediaControllerRemoved => {
-                Message::MediaControllerRemoved(
-                    MediaControllerRemovedData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::MediaCommand => {
-                Message::MediaCommand(
-                    MediaCommandData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::MediaCommandResult => {
-                Message::MediaCommandResult(
-                    MediaCommandResultData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::MediaState => {
-                Message::MediaState(
-                    MediaStateData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::Gamepad => {
-                Message::Gamepad(
-                    GamepadData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::SystemTextConfiguration => {
-                Message::SystemTextConfiguration(
-                    TextConfigurationData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::SystemTextInput => {
-                Message::SystemTextInput(
-                    SystemTextInputData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::SystemTouch => {
-                Message::SystemTouch(
-                    TouchData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::SystemTextAcknowledge => {
-                Message::SystemTextAcknowledge(
-                    SystemTextAcknowledgeData::from_raw_bytes(&decrypted_buf)?
-                )
-            },
-            MessageType::SystemTextDone => {
-                Message::SystemTextDone(
-                    SystemTextDoneData::from_raw_bytes(&decrypted_buf)?
-                )
-            }
-            _ => Message::Null
+        let message = if header.flags.is_fragment {
+            Message::Fragment(
+                header.flags.msg_type,
+                FragmentData::from_raw_bytes(&decrypted_buf)?
+            )
+        } else {
+            Message::read_payload(header.flags.msg_type, &decrypted_buf)?
         };
 
         Ok(Packet::Message(
@@ -363,9 +228,9 @@ impl Packet {
             Packet::DiscoveryResponse(ref header, ref data) => {
                 Packet::write_unprotected(write, header, data)?;
             },
-            Packet::ConnectRequest(ref header, ref unprotected_data, _) => {
-                header.write(write)?;
-                unprotected_data.write(write)?;
+            Packet::ConnectRequest(ref header, ref unprotected_data, ref protected_data) => {
+                let internal_state = state.ensure_connected()?;
+                Packet::write_protected(write, &internal_state.crypto, &unprotected_data.iv, header, unprotected_data, protected_data)?;
             },
             Packet::ConnectResponse(ref header, ref unprotected_data, ref protected_data) => {
                 let internal_state = state.ensure_connected()?;
@@ -389,8 +254,8 @@ impl Packet {
         let unprotected_len = write.position() - header_len;
         header_clone.set_unprotected_payload_length(unprotected_len as u16);
         write.set_position(0);
-        header.write(write)?;
-        // TODO: Should this be safe and move the cursor to the end?q
+        header_clone.write(write)?;
+        write.set_position(header_len + unprotected_len);
         Ok(())
     }
 
@@ -449,6 +314,9 @@ impl Packet {
         let mut protected_buf = Vec::<u8>::new();
         let mut decrypted_buf = vec![0u8; protected_payload_length];
         let buf_size = reader.read_to_end(&mut protected_buf)?;
+        if buf_size < 32 {
+            return Err(ReadError::TooShort(buf_size));
+        }
         &protected_buf.split_off(buf_size - 32);
         crypto.decrypt(iv, &protected_buf, &mut decrypted_buf).map_err(ReadError::Decrypt)?;
         Ok(decrypted_buf)
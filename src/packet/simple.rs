@@ -1,7 +1,7 @@
 use std::io::{Read, Write};
 
-use ::packet::{Type, Header};
-use ::util::{SGString, UUID, PublicKey, Certificate};
+use crate::packet::{Type, Header};
+use crate::util::{SGString, UUID, PublicKey, Certificate};
 
 use protocol;
 use protocol::Parcel;
@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use protocol::DynArray;
+
+use crate::packet::message::AcknowledgeData;
+
+/// How long to wait for an acknowledgement before the first retransmission
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(250);
+/// How often a message is sent before we give up on it
+const MAX_ATTEMPTS: u32 = 5;
+/// How many out of order sequence numbers are remembered before a gap is given up on
+const MAX_OUT_OF_ORDER: usize = 1024;
+
+struct Pending {
+    data: Vec<u8>,
+    attempts: u32,
+    timeout: Duration,
+    next_retransmit: Instant
+}
+
+/// Keeps track of acknowledgements in both directions
+///
+/// Outgoing messages that asked for an acknowledgement are kept around in
+/// serialized form and retransmitted with an exponential backoff until the
+/// console acknowledges them. Incoming sequence numbers are recorded so
+/// duplicates can be detected and acknowledgements carry a low watermark.
+///
+/// The watermark starts right below the first sequence number received, the
+/// console doesn't necessarily count from 1 when we join mid-stream.
+pub struct AckTracker {
+    pending: BTreeMap<u32, Pending>,
+    low_watermark: Option<u32>,
+    received: BTreeSet<u32>
+}
+
+impl AckTracker {
+    pub fn new() -> Self {
+        AckTracker {
+            pending: BTreeMap::new(),
+            low_watermark: None,
+            received: BTreeSet::new()
+        }
+    }
+
+    /// Remembers a sent message until the console acknowledges it
+    ///
+    /// # Arguments
+    /// * sequence_number - the sequence number of the message
+    /// * data - the serialized packet, sent again as-is on retransmission
+    /// * now - the time the packet was first sent
+    pub fn track(&mut self, sequence_number: u32, data: Vec<u8>, now: Instant) {
+        self.pending.insert(sequence_number, Pending {
+            data,
+            attempts: 1,
+            timeout: INITIAL_RETRANSMIT_TIMEOUT,
+            next_retransmit: now + INITIAL_RETRANSMIT_TIMEOUT
+        });
+    }
+
+    pub fn is_pending(&self, sequence_number: u32) -> bool {
+        self.pending.contains_key(&sequence_number)
+    }
+
+    /// Applies an acknowledgement received from the console
+    ///
+    /// Returns the sequence numbers the console rejected, those won't be retransmitted.
+    pub fn acknowledge(&mut self, ack: &AcknowledgeData) -> Vec<u32> {
+        let below_watermark: Vec<u32> = self.pending.range(..=ack.low_watermark)
+            .map(|(sequence_number, _)| *sequence_number)
+            .collect();
+
+        for sequence_number in below_watermark.iter()
+            .chain(ack.processed_list.elements.iter())
+            .chain(ack.rejected_list.elements.iter()) {
+            self.pending.remove(sequence_number);
+        }
+
+        ack.rejected_list.elements.clone()
+    }
+
+    /// Returns the next message that is due for retransmission and backs off its timer
+    ///
+    /// Gives up on a message once it was sent `MAX_ATTEMPTS` times, returning
+    /// its sequence number as the error.
+    pub fn poll_retransmit(&mut self, now: Instant) -> Result<Option<Vec<u8>>, u32> {
+        let due = self.pending.iter()
+            .find(|&(_, pending)| pending.next_retransmit <= now)
+            .map(|(sequence_number, _)| *sequence_number);
+
+        let sequence_number = match due {
+            Some(sequence_number) => sequence_number,
+            None => return Ok(None)
+        };
+
+        if self.pending[&sequence_number].attempts >= MAX_ATTEMPTS {
+            self.pending.remove(&sequence_number);
+            return Err(sequence_number);
+        }
+
+        let pending = self.pending.get_mut(&sequence_number).unwrap();
+        pending.attempts += 1;
+        pending.timeout *= 2;
+        pending.next_retransmit = now + pending.timeout;
+
+        Ok(Some(pending.data.clone()))
+    }
+
+    /// The next time `poll_retransmit` has something to do
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.next_retransmit).min()
+    }
+
+    /// Records a sequence number received from the console
+    ///
+    /// Returns false if the message was seen before, messages older than
+    /// the first one received count as seen.
+    pub fn receive(&mut self, sequence_number: u32) -> bool {
+        let mut low_watermark = *self.low_watermark.get_or_insert(sequence_number.saturating_sub(1));
+        if sequence_number <= low_watermark || !self.received.insert(sequence_number) {
+            return false;
+        }
+
+        if self.received.len() > MAX_OUT_OF_ORDER {
+            // Whatever was missing isn't going to show up anymore
+            low_watermark = *self.received.iter().next().unwrap() - 1;
+        }
+
+        while self.received.remove(&(low_watermark + 1)) {
+            low_watermark += 1;
+        }
+
+        self.low_watermark = Some(low_watermark);
+        true
+    }
+
+    /// The highest sequence number up to which every message was received
+    ///
+    /// 0 until the first message arrives.
+    pub fn low_watermark(&self) -> u32 {
+        self.low_watermark.unwrap_or(0)
+    }
+
+    /// Builds the acknowledgement for a received message
+    pub fn acknowledgement(&self, sequence_number: u32) -> AcknowledgeData {
+        AcknowledgeData {
+            low_watermark: self.low_watermark(),
+            processed_list: DynArray::new(vec![sequence_number]),
+            rejected_list: DynArray::new(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ack(low_watermark: u32, processed: Vec<u32>, rejected: Vec<u32>) -> AcknowledgeData {
+        AcknowledgeData {
+            low_watermark,
+            processed_list: DynArray::new(processed),
+            rejected_list: DynArray::new(rejected)
+        }
+    }
+
+    #[test]
+    fn acknowledge_works() {
+        let now = Instant::now();
+        let mut tracker = AckTracker::new();
+        tracker.track(1, vec![1], now);
+        tracker.track(2, vec![2], now);
+        tracker.track(3, vec![3], now);
+        tracker.track(4, vec![4], now);
+
+        let rejected = tracker.acknowledge(&ack(1, vec![3], vec![4]));
+
+        assert_eq!(rejected, vec![4]);
+        assert!(!tracker.is_pending(1));
+        assert!(tracker.is_pending(2));
+        assert!(!tracker.is_pending(3));
+        assert!(!tracker.is_pending(4));
+    }
+
+    #[test]
+    fn retransmit_backs_off() {
+        let now = Instant::now();
+        let mut tracker = AckTracker::new();
+        tracker.track(1, vec![0xaa], now);
+
+        assert_eq!(tracker.poll_retransmit(now).unwrap(), None);
+        assert_eq!(tracker.next_timeout(), Some(now + INITIAL_RETRANSMIT_TIMEOUT));
+
+        let first = now + INITIAL_RETRANSMIT_TIMEOUT;
+        assert_eq!(tracker.poll_retransmit(first).unwrap(), Some(vec![0xaa]));
+        assert_eq!(tracker.next_timeout(), Some(first + INITIAL_RETRANSMIT_TIMEOUT * 2));
+        assert_eq!(tracker.poll_retransmit(first).unwrap(), None);
+    }
+
+    #[test]
+    fn retransmit_gives_up() {
+        let mut now = Instant::now();
+        let mut tracker = AckTracker::new();
+        tracker.track(7, vec![0xaa], now);
+
+        for _ in 1..MAX_ATTEMPTS {
+            now = tracker.next_timeout().unwrap();
+            assert!(tracker.poll_retransmit(now).unwrap().is_some());
+        }
+
+        now = tracker.next_timeout().unwrap();
+        assert_eq!(tracker.poll_retransmit(now), Err(7));
+        assert!(!tracker.is_pending(7));
+        assert_eq!(tracker.next_timeout(), None);
+    }
+
+    #[test]
+    fn acknowledged_messages_are_not_retransmitted() {
+        let now = Instant::now();
+        let mut tracker = AckTracker::new();
+        tracker.track(1, vec![0xaa], now);
+        tracker.acknowledge(&ack(0, vec![1], vec![]));
+
+        assert_eq!(tracker.poll_retransmit(now + Duration::from_secs(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn receive_detects_duplicates() {
+        let mut tracker = AckTracker::new();
+
+        assert!(tracker.receive(1));
+        assert!(tracker.receive(3));
+        assert!(!tracker.receive(3));
+        assert!(!tracker.receive(1));
+        assert_eq!(tracker.low_watermark(), 1);
+
+        assert!(tracker.receive(2));
+        assert_eq!(tracker.low_watermark(), 3);
+        assert_eq!(tracker.acknowledgement(2), ack(3, vec![2], vec![]));
+    }
+
+    #[test]
+    fn receive_starts_at_the_first_sequence_number() {
+        let mut tracker = AckTracker::new();
+        assert_eq!(tracker.low_watermark(), 0);
+
+        assert!(tracker.receive(500));
+        assert_eq!(tracker.low_watermark(), 500);
+
+        assert!(tracker.receive(502));
+        assert!(!tracker.receive(499));
+        assert_eq!(tracker.low_watermark(), 500);
+
+        assert!(tracker.receive(501));
+        assert_eq!(tracker.low_watermark(), 502);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use crate::constants;
+use crate::packet::message::{StartChannelRequestData, StartChannelResponseData, StopChannelData};
+use crate::util::UUID;
+
+/// The console services a channel can be opened for
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum ServiceChannel {
+    SystemInput,
+    SystemInputTVRemote,
+    SystemMedia,
+    SystemText,
+    SystemBroadcast,
+    /// The channel to the title with the given title id, used for title messages like sensor data
+    Title(u32)
+}
+
+impl ServiceChannel {
+    pub fn uuid(&self) -> &'static UUID<u8> {
+        match *self {
+            ServiceChannel::SystemInput => &constants::uuid::SYSTEM_INPUT,
+            ServiceChannel::SystemInputTVRemote => &constants::uuid::SYSTEM_INPUT_TV_REMOTE,
+            ServiceChannel::SystemMedia => &constants::uuid::SYSTEM_MEDIA,
+            ServiceChannel::SystemText => &constants::uuid::SYSTEM_TEXT,
+            ServiceChannel::SystemBroadcast => &constants::uuid::SYSTEM_BROADCAST,
+            ServiceChannel::Title(_) => &constants::uuid::NONE
+        }
+    }
+
+    /// The title id that goes into the request, 0 for system services
+    pub fn title_id(&self) -> u32 {
+        match *self {
+            ServiceChannel::Title(title_id) => title_id,
+            _ => 0
+        }
+    }
+}
+
+/// What became of a channel request
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ChannelEvent {
+    Opened(ServiceChannel, u64),
+    Failed(ServiceChannel, u32)
+}
+
+/// Keeps track of the service channels opened with the console
+///
+/// Every `StartChannelRequest` carries an id of our choosing, the console
+/// echoes it in its `StartChannelResponse` together with the channel id all
+/// messages for that service have to be sent on.
+pub struct ChannelManager {
+    next_request_id: u32,
+    pending: HashMap<u32, ServiceChannel>,
+    open: HashMap<ServiceChannel, u64>
+}
+
+impl ChannelManager {
+    pub fn new() -> Self {
+        ChannelManager {
+            next_request_id: 1,
+            pending: HashMap::new(),
+            open: HashMap::new()
+        }
+    }
+
+    /// Builds the request to open a channel for `service`
+    pub fn start(&mut self, service: ServiceChannel) -> StartChannelRequestData {
+        let channel_request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.pending.insert(channel_request_id, service);
+
+        StartChannelRequestData {
+            channel_request_id,
+            title_id: service.title_id(),
+            service: service.uuid().clone(),
+            activity_id: 0
+        }
+    }
+
+    /// Matches a response from the console with our request
+    ///
+    /// Returns `None` for responses to requests we didn't make.
+    pub fn handle_response(&mut self, response: &StartChannelResponseData) -> Option<ChannelEvent> {
+        let service = self.pending.remove(&response.channel_request_id)?;
+
+        if response.result != 0 {
+            return Some(ChannelEvent::Failed(service, response.result));
+        }
+
+        self.open.insert(service, response.target_channel_id);
+        Some(ChannelEvent::Opened(service, response.target_channel_id))
+    }
+
+    /// Forgets about the channel for `service`, returning the message that closes it
+    pub fn stop(&mut self, service: ServiceChannel) -> Option<StopChannelData> {
+        self.open.remove(&service).map(|target_channel_id| StopChannelData {
+            target_channel_id
+        })
+    }
+
+    /// Forgets about a channel the console closed
+    pub fn handle_stop(&mut self, stop: &StopChannelData) -> Option<ServiceChannel> {
+        let service = self.service(stop.target_channel_id)?;
+        self.open.remove(&service);
+        Some(service)
+    }
+
+    /// The channel id messages for `service` go on, if the channel is open
+    pub fn channel_id(&self, service: ServiceChannel) -> Option<u64> {
+        self.open.get(&service).cloned()
+    }
+
+    /// The service an open channel id belongs to
+    pub fn service(&self, channel_id: u64) -> Option<ServiceChannel> {
+        self.open.iter()
+            .find(|&(_, id)| *id == channel_id)
+            .map(|(service, _)| *service)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(channel_request_id: u32, target_channel_id: u64, result: u32) -> StartChannelResponseData {
+        StartChannelResponseData {
+            channel_request_id,
+            target_channel_id,
+            result
+        }
+    }
+
+    #[test]
+    fn open_works() {
+        let mut channels = ChannelManager::new();
+        let request = channels.start(ServiceChannel::SystemInput);
+
+        assert_eq!(request.channel_request_id, 1);
+        assert_eq!(request.service, *constants::uuid::SYSTEM_INPUT);
+        assert_eq!(channels.channel_id(ServiceChannel::SystemInput), None);
+
+        let event = channels.handle_response(&response(1, 148, 0));
+        assert_eq!(event, Some(ChannelEvent::Opened(ServiceChannel::SystemInput, 148)));
+        assert_eq!(channels.channel_id(ServiceChannel::SystemInput), Some(148));
+        assert_eq!(channels.service(148), Some(ServiceChannel::SystemInput));
+    }
+
+    #[test]
+    fn responses_are_matched_by_request_id() {
+        let mut channels = ChannelManager::new();
+        channels.start(ServiceChannel::SystemInput);
+        channels.start(ServiceChannel::SystemMedia);
+
+        channels.handle_response(&response(2, 153, 0));
+        channels.handle_response(&response(1, 148, 0));
+
+        assert_eq!(channels.channel_id(ServiceChannel::SystemMedia), Some(153));
+        assert_eq!(channels.channel_id(ServiceChannel::SystemInput), Some(148));
+    }
+
+    #[test]
+    fn unknown_and_failed_responses_work() {
+        let mut channels = ChannelManager::new();
+        channels.start(ServiceChannel::SystemText);
+
+        assert_eq!(channels.handle_response(&response(5, 154, 0)), None);
+        assert_eq!(channels.handle_response(&response(1, 0, 0x8000_0005)), Some(ChannelEvent::Failed(ServiceChannel::SystemText, 0x8000_0005)));
+        assert_eq!(channels.channel_id(ServiceChannel::SystemText), None);
+    }
+
+    #[test]
+    fn stop_works() {
+        let mut channels = ChannelManager::new();
+        channels.start(ServiceChannel::SystemMedia);
+        channels.handle_response(&response(1, 153, 0));
+
+        assert_eq!(channels.stop(ServiceChannel::SystemMedia), Some(StopChannelData { target_channel_id: 153 }));
+        assert_eq!(channels.stop(ServiceChannel::SystemMedia), None);
+        assert_eq!(channels.service(153), None);
+    }
+
+    #[test]
+    fn title_channels_work() {
+        let mut channels = ChannelManager::new();
+        let request = channels.start(ServiceChannel::Title(0x3d705025));
+
+        assert_eq!(request.title_id, 0x3d705025);
+        assert_eq!(request.service, *constants::uuid::NONE);
+
+        channels.handle_response(&response(1, 160, 0));
+        assert_eq!(channels.service(160), Some(ServiceChannel::Title(0x3d705025)));
+    }
+}
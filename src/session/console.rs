@@ -0,0 +1,249 @@
+use crate::packet::message::*;
+use crate::state::PairingState;
+
+/// Something that changed on the console
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleEvent {
+    TitleLaunched(ActiveTitle),
+    TitleClosed(ActiveTitle),
+    /// A title moved or gained or lost focus, along with where it was before
+    TitleMoved(ActiveTitle, TitleDisposition),
+    MediaChanged(MediaStateData),
+    PairingChanged(PairingState),
+    SurfaceChanged(ActiveSurfaceChangeData)
+}
+
+/// The current picture of the console, put together from the messages it sends
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConsoleState {
+    pub live_tv_provider: u32,
+    /// The OS version as major, minor and build number
+    pub version: (u32, u32, u32),
+    pub locale: String,
+    pub active_titles: Vec<ActiveTitle>,
+    /// The last media state of any title
+    pub media: Option<MediaStateData>,
+    pub pairing_state: Option<PairingState>,
+    pub active_surface: Option<ActiveSurfaceChangeData>
+}
+
+impl ConsoleState {
+    pub fn new() -> Self {
+        ConsoleState::default()
+    }
+
+    /// The title that has focus, if any
+    pub fn focused_title(&self) -> Option<&ActiveTitle> {
+        self.active_titles.iter().find(|title| title.title_disposition.has_focus)
+    }
+
+    pub fn title(&self, title_id: u32) -> Option<&ActiveTitle> {
+        self.active_titles.iter().find(|title| title.title_id == title_id)
+    }
+
+    /// Whether a `ConsoleStatus` has arrived yet, the console always reports its version in it
+    pub fn has_status(&self) -> bool {
+        self.version != (0, 0, 0)
+    }
+
+    /// Folds a message from the console into the snapshot
+    ///
+    /// Returns what changed, messages that don't describe the console are ignored.
+    pub fn apply(&mut self, message: &Message) -> Vec<ConsoleEvent> {
+        match *message {
+            Message::ConsoleStatus(ref status) => self.apply_status(status),
+            Message::MediaState(ref media) => {
+                if self.media.as_ref() == Some(media) {
+                    return vec![];
+                }
+                self.media = Some(media.clone());
+                vec![ConsoleEvent::MediaChanged(media.clone())]
+            },
+            Message::PairedIdentityStateChanged(ref data) => {
+                if self.pairing_state == Some(data.state) {
+                    return vec![];
+                }
+                self.pairing_state = Some(data.state);
+                vec![ConsoleEvent::PairingChanged(data.state)]
+            },
+            Message::ActiveSurfaceChange(ref surface) => {
+                self.active_surface = Some(surface.clone());
+                vec![ConsoleEvent::SurfaceChanged(surface.clone())]
+            },
+            _ => vec![]
+        }
+    }
+
+    fn apply_status(&mut self, status: &ConsoleStatusData) -> Vec<ConsoleEvent> {
+        self.live_tv_provider = status.live_tv_provider;
+        self.version = (status.major_version, status.minor_version, status.build_number);
+        self.locale = status.locale.to_str();
+
+        let titles = &status.active_titles.elements;
+        let mut events = Vec::new();
+
+        for old in self.active_titles.iter() {
+            if !titles.iter().any(|title| title.title_id == old.title_id) {
+                events.push(ConsoleEvent::TitleClosed(old.clone()));
+            }
+        }
+
+        for title in titles.iter() {
+            match self.title(title.title_id) {
+                None => events.push(ConsoleEvent::TitleLaunched(title.clone())),
+                Some(old) if old.title_disposition != title.title_disposition => {
+                    events.push(ConsoleEvent::TitleMoved(title.clone(), old.title_disposition));
+                },
+                Some(_) => {}
+            }
+        }
+
+        self.active_titles = titles.clone();
+        events
+    }
+}
+
+/// Picks the title a `TitleLaunch` started out of the statuses that follow it
+///
+/// Launches by title id wait for that title to become active. For other URIs
+/// the title has to be new and focused. Without a status from before the
+/// launch the first one that arrives only tells which titles were already
+/// running.
+pub struct LaunchWatch {
+    title_id: Option<u32>,
+    previous: Option<Vec<u32>>
+}
+
+impl LaunchWatch {
+    /// Starts watching for `launch`, `console` being the state before sending it
+    pub fn new(launch: &TitleLaunchData, console: &ConsoleState) -> Self {
+        let previous = if console.has_status() {
+            Some(console.active_titles.iter().map(|title| title.title_id).collect())
+        } else {
+            None
+        };
+
+        LaunchWatch {
+            title_id: launch.launched_title_id(),
+            previous
+        }
+    }
+
+    /// Returns the launched title once `message` shows it
+    pub fn check(&mut self, message: &Message) -> Option<ActiveTitle> {
+        let titles = match *message {
+            Message::ConsoleStatus(ref status) => &status.active_titles.elements,
+            _ => return None
+        };
+
+        if let Some(title_id) = self.title_id {
+            return titles.iter().find(|title| title.title_id == title_id).cloned();
+        }
+
+        match self.previous {
+            Some(ref previous) => titles.iter()
+                .find(|title| title.title_disposition.has_focus && !previous.contains(&title.title_id))
+                .cloned(),
+            None => {
+                self.previous = Some(titles.iter().map(|title| title.title_id).collect());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use protocol::DynArray;
+
+    use super::*;
+    use crate::constants;
+    use crate::util::SGString;
+
+    fn title(title_id: u32, has_focus: bool, location: TitleLocation) -> ActiveTitle {
+        ActiveTitle {
+            title_id,
+            title_disposition: TitleDisposition {
+                has_focus,
+                location
+            },
+            product_id: constants::uuid::NONE.clone(),
+            sandbox_id: constants::uuid::NONE.clone(),
+            aum: SGString::from_str(String::new())
+        }
+    }
+
+    fn status(titles: Vec<ActiveTitle>) -> Message {
+        Message::ConsoleStatus(ConsoleStatusData {
+            live_tv_provider: 0,
+            major_version: 10,
+            minor_version: 0,
+            build_number: 14393,
+            locale: SGString::from_str(String::from("en-US")),
+            active_titles: DynArray::new(titles)
+        })
+    }
+
+    #[test]
+    fn status_is_folded() {
+        let mut console = ConsoleState::new();
+        let home = title(714681658, true, TitleLocation::StartView);
+
+        let events = console.apply(&status(vec![home.clone()]));
+        assert_eq!(events, vec![ConsoleEvent::TitleLaunched(home.clone())]);
+        assert_eq!(console.version, (10, 0, 14393));
+        assert_eq!(console.locale, "en-US");
+        assert_eq!(console.focused_title(), Some(&home));
+
+        assert_eq!(console.apply(&status(vec![home])), vec![]);
+    }
+
+    #[test]
+    fn title_changes_are_reported() {
+        let mut console = ConsoleState::new();
+        let home = title(714681658, true, TitleLocation::StartView);
+        let game = title(0x3d705025, true, TitleLocation::Full);
+        console.apply(&status(vec![home.clone()]));
+
+        let snapped_home = title(714681658, false, TitleLocation::Snapped);
+        let events = console.apply(&status(vec![snapped_home.clone(), game.clone()]));
+        assert_eq!(events, vec![
+            ConsoleEvent::TitleMoved(snapped_home, home.title_disposition),
+            ConsoleEvent::TitleLaunched(game.clone())
+        ]);
+
+        let events = console.apply(&status(vec![game.clone()]));
+        assert_eq!(events, vec![ConsoleEvent::TitleClosed(title(714681658, false, TitleLocation::Snapped))]);
+        assert_eq!(console.focused_title(), Some(&game));
+    }
+
+    #[test]
+    fn launches_wait_for_a_new_focused_title() {
+        let home = title(714681658, true, TitleLocation::StartView);
+        let game = title(0x3d705025, true, TitleLocation::Full);
+        let launch = TitleLaunchData::aum("Game_8wekyb3d8bbwe!App", TitleLocation::Full);
+
+        // Without a status the first one only shows what was running before
+        let mut watch = LaunchWatch::new(&launch, &ConsoleState::new());
+        assert_eq!(watch.check(&status(vec![home.clone()])), None);
+        assert_eq!(watch.check(&status(vec![title(714681658, false, TitleLocation::StartView), game.clone()])), Some(game.clone()));
+
+        let mut console = ConsoleState::new();
+        console.apply(&status(vec![home.clone()]));
+        let mut watch = LaunchWatch::new(&TitleLaunchData::title_id(0x3d705025, TitleLocation::Full), &console);
+        assert_eq!(watch.check(&status(vec![home])), None);
+        assert_eq!(watch.check(&status(vec![game.clone()])), Some(game));
+    }
+
+    #[test]
+    fn pairing_changes_are_reported() {
+        let mut console = ConsoleState::new();
+        let paired = Message::PairedIdentityStateChanged(PairedIdentityStateChangedData {
+            state: PairingState::Paired
+        });
+
+        assert_eq!(console.apply(&paired), vec![ConsoleEvent::PairingChanged(PairingState::Paired)]);
+        assert_eq!(console.apply(&paired), vec![]);
+        assert_eq!(console.pairing_state, Some(PairingState::Paired));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use protocol::DynArray;
+
+use crate::packet::message::FragmentData;
+
+/// The largest message payload that is sent without splitting it up
+pub const MAX_PAYLOAD_LEN: usize = 1024;
+/// How many fragments are buffered before the oldest ones are dropped
+const MAX_BUFFERED: usize = 256;
+
+/// Collects fragments until a message can be put back together
+///
+/// Every fragment is a message of its own with its own sequence number,
+/// `sequence_begin` and `sequence_end` (exclusive) tell which sequence
+/// numbers make up the whole message.
+pub struct FragmentAssembler {
+    fragments: BTreeMap<u32, Vec<u8>>
+}
+
+impl FragmentAssembler {
+    pub fn new() -> Self {
+        FragmentAssembler {
+            fragments: BTreeMap::new()
+        }
+    }
+
+    /// Adds a fragment, returning the complete payload once all of its siblings arrived
+    ///
+    /// # Arguments
+    /// * sequence_number - the sequence number from the header of the fragment
+    /// * fragment - the fragment itself
+    pub fn add(&mut self, sequence_number: u32, fragment: &FragmentData) -> Option<Vec<u8>> {
+        if self.fragments.len() >= MAX_BUFFERED {
+            // Whatever these belonged to is never going to be completed
+            let oldest = *self.fragments.keys().next().unwrap();
+            self.fragments.remove(&oldest);
+        }
+        self.fragments.insert(sequence_number, fragment.data.elements.clone());
+
+        let range = fragment.sequence_begin..fragment.sequence_end;
+        if range.is_empty() || !range.clone().all(|sequence_number| self.fragments.contains_key(&sequence_number)) {
+            return None;
+        }
+
+        let mut payload = Vec::new();
+        for sequence_number in range {
+            payload.extend(self.fragments.remove(&sequence_number).unwrap());
+        }
+
+        Some(payload)
+    }
+}
+
+/// Splits a serialized message payload into fragments
+///
+/// # Arguments
+/// * payload - the serialized message
+/// * sequence_begin - the sequence number the first fragment will be sent with,
+///   the others need to follow without gaps
+pub fn split(payload: &[u8], sequence_begin: u32) -> Vec<FragmentData> {
+    let sequence_end = sequence_begin + ((payload.len() + MAX_PAYLOAD_LEN - 1) / MAX_PAYLOAD_LEN) as u32;
+
+    payload.chunks(MAX_PAYLOAD_LEN)
+        .map(|chunk| FragmentData {
+            sequence_begin,
+            sequence_end,
+            data: DynArray::new(chunk.to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn payload() -> Vec<u8> {
+        (0..MAX_PAYLOAD_LEN * 2 + 10).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn split_works() {
+        let fragments = split(&payload(), 10);
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0].sequence_begin, 10);
+        assert_eq!(fragments[0].sequence_end, 13);
+        assert_eq!(fragments[0].data.elements.len(), MAX_PAYLOAD_LEN);
+        assert_eq!(fragments[2].data.elements.len(), 10);
+    }
+
+    #[test]
+    fn reassemble_works() {
+        let mut assembler = FragmentAssembler::new();
+        let fragments = split(&payload(), 10);
+
+        assert_eq!(assembler.add(10, &fragments[0]), None);
+        assert_eq!(assembler.add(11, &fragments[1]), None);
+        assert_eq!(assembler.add(12, &fragments[2]), Some(payload()));
+    }
+
+    #[test]
+    fn reassemble_out_of_order_works() {
+        let mut assembler = FragmentAssembler::new();
+        let fragments = split(&payload(), 10);
+
+        assert_eq!(assembler.add(12, &fragments[2]), None);
+        assert_eq!(assembler.add(10, &fragments[0]), None);
+        assert_eq!(assembler.add(11, &fragments[1]), Some(payload()));
+    }
+
+    #[test]
+    fn reassemble_waits_for_missing_fragments() {
+        let mut assembler = FragmentAssembler::new();
+        let fragments = split(&payload(), 10);
+
+        assert_eq!(assembler.add(10, &fragments[0]), None);
+        assert_eq!(assembler.add(12, &fragments[2]), None);
+        assert_eq!(assembler.add(12, &fragments[2]), None);
+    }
+}
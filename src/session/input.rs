@@ -0,0 +1,103 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::packet::message::{GamepadButtons, GamepadData};
+
+/// Keeps track of the buttons held on the virtual gamepad
+///
+/// The console treats every report as the complete state of the gamepad, so
+/// releasing one button means sending a report with all the others still set.
+/// Reports with a timestamp that isn't newer than the previous one are
+/// dropped by the console, so timestamps are bumped to keep them increasing.
+pub struct Gamepad {
+    buttons: GamepadButtons,
+    last_timestamp: u64
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Gamepad {
+            buttons: GamepadButtons::empty(),
+            last_timestamp: 0
+        }
+    }
+
+    /// The buttons currently held down
+    pub fn buttons(&self) -> GamepadButtons {
+        self.buttons
+    }
+
+    /// Builds the report holding `buttons` down in addition to the ones already held
+    pub fn hold(&mut self, buttons: GamepadButtons, timestamp: u64) -> GamepadData {
+        self.buttons.insert(buttons);
+        self.report(timestamp)
+    }
+
+    /// Builds the report letting go of `buttons`
+    pub fn release(&mut self, buttons: GamepadButtons, timestamp: u64) -> GamepadData {
+        self.buttons.remove(buttons);
+        self.report(timestamp)
+    }
+
+    /// Builds the pair of reports pressing and releasing `buttons`
+    pub fn press(&mut self, buttons: GamepadButtons, timestamp: u64) -> [GamepadData; 2] {
+        let down = self.hold(buttons, timestamp);
+        let up = self.release(buttons, timestamp);
+        [down, up]
+    }
+
+    fn report(&mut self, timestamp: u64) -> GamepadData {
+        let timestamp = timestamp.max(self.last_timestamp + 1);
+        self.last_timestamp = timestamp;
+
+        GamepadData::new()
+            .timestamp(timestamp)
+            .buttons(self.buttons)
+    }
+}
+
+/// The current time in milliseconds, as used for input report timestamps
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn press_works() {
+        let mut gamepad = Gamepad::new();
+        let [down, up] = gamepad.press(GamepadButtons::A, 1000);
+
+        assert_eq!(down.buttons, GamepadButtons::A);
+        assert_eq!(down.timestamp, 1000);
+        assert_eq!(up.buttons, GamepadButtons::empty());
+        assert_eq!(up.timestamp, 1001);
+        assert_eq!(gamepad.buttons(), GamepadButtons::empty());
+    }
+
+    #[test]
+    fn held_buttons_stay_set() {
+        let mut gamepad = Gamepad::new();
+        gamepad.hold(GamepadButtons::LEFT_SHOULDER, 1000);
+        let [down, up] = gamepad.press(GamepadButtons::B, 2000);
+
+        assert_eq!(down.buttons, GamepadButtons::LEFT_SHOULDER | GamepadButtons::B);
+        assert_eq!(up.buttons, GamepadButtons::LEFT_SHOULDER);
+
+        let released = gamepad.release(GamepadButtons::LEFT_SHOULDER, 3000);
+        assert_eq!(released.buttons, GamepadButtons::empty());
+    }
+
+    #[test]
+    fn timestamps_increase() {
+        let mut gamepad = Gamepad::new();
+        gamepad.hold(GamepadButtons::X, 5000);
+
+        // The clock went backwards
+        let report = gamepad.release(GamepadButtons::X, 4000);
+        assert_eq!(report.timestamp, 5001);
+    }
+}
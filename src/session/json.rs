@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+
+use rustc_serialize::base64::FromBase64;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::packet::message::JsonData;
+use crate::util::SGString;
+
+/// How many incomplete messages are buffered before the oldest ones are dropped
+const MAX_BUFFERED: usize = 32;
+/// The longest text a `JsonData` can carry, its length is sent as u16
+const MAX_DATAGRAM_SIZE: usize = 0xffff;
+
+impl JsonData {
+    /// Serializes `value` into a JSON message
+    pub fn from_value<T: Serialize>(value: &T) -> serde_json::Result<Self> {
+        Ok(JsonData {
+            text: SGString::from_str(serde_json::to_string(value)?)
+        })
+    }
+
+    /// Parses the text of the message, `serde_json::Value` takes any JSON
+    pub fn parse<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(self.text.value())
+    }
+}
+
+/// A piece of a JSON message the console split up, sent as a JSON message of its own
+///
+/// ```json
+/// {"datagram_id": "1", "datagram_size": "2730", "fragment_offset": "0", "fragment_length": "1024", "fragment_data": "<base64>"}
+/// ```
+struct Fragment {
+    datagram_id: u64,
+    datagram_size: usize,
+    offset: usize,
+    data: Vec<u8>
+}
+
+impl Fragment {
+    /// Reads a fragment, `None` if `value` isn't one or doesn't fit into its message
+    fn from_value(value: &Value) -> Option<Self> {
+        let field = |name: &str| value.get(name).and_then(number);
+
+        let data = value.get("fragment_data")?.as_str()?.from_base64().ok()?;
+        if field("fragment_length").map_or(false, |length| length != data.len() as u64) {
+            return None;
+        }
+
+        let datagram_size = field("datagram_size")?;
+        let offset = field("fragment_offset")?;
+        if datagram_size > MAX_DATAGRAM_SIZE as u64 || offset.saturating_add(data.len() as u64) > datagram_size {
+            return None;
+        }
+
+        Some(Fragment {
+            datagram_id: field("datagram_id")?,
+            datagram_size: datagram_size as usize,
+            offset: offset as usize,
+            data
+        })
+    }
+}
+
+/// The console sends the numbers of a fragment as strings, but accept actual numbers too
+fn number(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| value.as_str()?.parse().ok())
+}
+
+struct Datagram {
+    size: usize,
+    fragments: BTreeMap<usize, Vec<u8>>
+}
+
+impl Datagram {
+    /// The whole text once the fragments cover it without gaps
+    fn payload(&self) -> Option<Vec<u8>> {
+        let mut payload = Vec::with_capacity(self.size);
+        for (&offset, data) in self.fragments.iter() {
+            if offset > payload.len() {
+                return None;
+            }
+            let overlap = (payload.len() - offset).min(data.len());
+            payload.extend_from_slice(&data[overlap..]);
+        }
+
+        if payload.len() < self.size {
+            return None;
+        }
+        payload.truncate(self.size);
+        Some(payload)
+    }
+}
+
+/// Puts JSON messages back together that the console split into JSON fragments
+///
+/// This is on top of the binary `Fragment` messages handled by `FragmentAssembler`,
+/// long JSON payloads are base64 encoded and spread over several `Json`
+/// messages instead.
+pub struct JsonAssembler {
+    datagrams: BTreeMap<u64, Datagram>
+}
+
+impl JsonAssembler {
+    pub fn new() -> Self {
+        JsonAssembler {
+            datagrams: BTreeMap::new()
+        }
+    }
+
+    /// Adds a JSON message, returning the complete message if there is one
+    ///
+    /// Messages that aren't fragments are returned as they are, fragments
+    /// give `None` until the last missing one arrives. Messages that turn out
+    /// not to be valid UTF-8 once they are complete are dropped.
+    pub fn add(&mut self, data: &JsonData) -> Option<JsonData> {
+        let fragment = match data.parse::<Value>().ok().as_ref().and_then(Fragment::from_value) {
+            Some(fragment) => fragment,
+            None => return Some(data.clone())
+        };
+
+        if !self.datagrams.contains_key(&fragment.datagram_id) && self.datagrams.len() >= MAX_BUFFERED {
+            // Whatever these belonged to is never going to be completed
+            let oldest = *self.datagrams.keys().next().unwrap();
+            self.datagrams.remove(&oldest);
+        }
+
+        let datagram = self.datagrams.entry(fragment.datagram_id)
+            .or_insert_with(|| Datagram {
+                size: fragment.datagram_size,
+                fragments: BTreeMap::new()
+            });
+        if datagram.size != fragment.datagram_size {
+            return None;
+        }
+        datagram.fragments.insert(fragment.offset, fragment.data);
+
+        let payload = datagram.payload()?;
+        self.datagrams.remove(&fragment.datagram_id);
+
+        String::from_utf8(payload).ok()
+            .map(|text| JsonData {
+                text: SGString::from_str(text)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(value: Value) -> JsonData {
+        JsonData::from_value(&value).unwrap()
+    }
+
+    fn fragments(text: &str, datagram_id: u64, len: usize) -> Vec<JsonData> {
+        text.as_bytes().chunks(len).enumerate()
+            .map(|(index, chunk)| message(json!({
+                "datagram_id": datagram_id.to_string(),
+                "datagram_size": text.len().to_string(),
+                "fragment_offset": (index * len).to_string(),
+                "fragment_length": chunk.len().to_string(),
+                "fragment_data": chunk.to_base64(STANDARD)
+            })))
+            .collect()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = json!({"request": "GetConfiguration", "params": null});
+        let data = JsonData::from_value(&value).unwrap();
+
+        assert_eq!(data.parse::<Value>().unwrap(), value);
+        assert!(data.parse::<u32>().is_err());
+    }
+
+    #[test]
+    fn plain_messages_pass_through() {
+        let mut assembler = JsonAssembler::new();
+        let data = message(json!({"response": "GetConfiguration"}));
+
+        assert_eq!(assembler.add(&data), Some(data));
+    }
+
+    #[test]
+    fn fragments_are_reassembled() {
+        let mut assembler = JsonAssembler::new();
+        let text = json!({"channels": vec!["channel"; 100]}).to_string();
+        let mut fragments = fragments(&text, 7, 250);
+        assert_eq!(fragments.len(), 5);
+
+        let last = fragments.remove(2);
+        for fragment in fragments.iter().rev() {
+            assert_eq!(assembler.add(fragment), None);
+        }
+        assert_eq!(assembler.add(&last).unwrap().text.to_str(), text);
+        assert!(assembler.datagrams.is_empty());
+    }
+
+    #[test]
+    fn fragments_outside_their_message_are_not_buffered() {
+        let mut assembler = JsonAssembler::new();
+        let huge = message(json!({
+            "datagram_id": "1",
+            "datagram_size": "18446744073709551615",
+            "fragment_offset": "0",
+            "fragment_length": "2",
+            "fragment_data": b"{}".to_base64(STANDARD)
+        }));
+        let past_the_end = message(json!({
+            "datagram_id": "2",
+            "datagram_size": "4",
+            "fragment_offset": "3",
+            "fragment_length": "2",
+            "fragment_data": b"{}".to_base64(STANDARD)
+        }));
+
+        assert_eq!(assembler.add(&huge), Some(huge));
+        assert_eq!(assembler.add(&past_the_end), Some(past_the_end));
+        assert!(assembler.datagrams.is_empty());
+    }
+
+    #[test]
+    fn numbers_are_accepted() {
+        let mut assembler = JsonAssembler::new();
+        let fragment = message(json!({
+            "datagram_id": 1,
+            "datagram_size": 2,
+            "fragment_offset": 0,
+            "fragment_length": 2,
+            "fragment_data": b"{}".to_base64(STANDARD)
+        }));
+
+        assert_eq!(assembler.add(&fragment).unwrap().text.to_str(), "{}");
+    }
+}
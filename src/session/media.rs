@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::packet::message::{MediaCommandData, MediaCommandResultData, MediaControlCommand};
+
+/// Hands out request ids for media commands and matches the console's results to them
+///
+/// The console answers every `MediaCommand` with a `MediaCommandResult`
+/// carrying the request id of the command.
+pub struct MediaCommands {
+    next_request_id: u64,
+    pending: HashMap<u64, MediaControlCommand>
+}
+
+impl MediaCommands {
+    pub fn new() -> Self {
+        MediaCommands {
+            next_request_id: 1,
+            pending: HashMap::new()
+        }
+    }
+
+    /// Builds the message for `command`
+    ///
+    /// # Arguments
+    /// * title_id - the title playing the media
+    /// * command - what to do
+    /// * seek_position - where to seek to in 100ns ticks, ignored unless `command` is `MediaControlCommand::Seek`
+    pub fn command(&mut self, title_id: u32, command: MediaControlCommand, seek_position: u64) -> MediaCommandData {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.pending.insert(request_id, command);
+
+        MediaCommandData {
+            request_id,
+            title_id,
+            command,
+            seek_position: match command {
+                MediaControlCommand::Seek => Some(seek_position),
+                _ => None
+            }
+        }
+    }
+
+    /// Matches a result from the console with our command
+    ///
+    /// Returns `None` for results to commands we didn't send.
+    pub fn handle_result(&mut self, result: &MediaCommandResultData) -> Option<MediaControlCommand> {
+        self.pending.remove(&result.request_id)
+    }
+
+    /// Whether the command with `request_id` still waits for its result
+    pub fn is_pending(&self, request_id: u64) -> bool {
+        self.pending.contains_key(&request_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_works() {
+        let mut commands = MediaCommands::new();
+        let play = commands.command(0x1234, MediaControlCommand::Play, 500);
+        let seek = commands.command(0x1234, MediaControlCommand::Seek, 500);
+
+        assert_eq!(play.request_id, 1);
+        assert_eq!(play.seek_position, None);
+        assert_eq!(seek.request_id, 2);
+        assert_eq!(seek.seek_position, Some(500));
+        assert!(commands.is_pending(1));
+        assert!(commands.is_pending(2));
+    }
+
+    #[test]
+    fn results_are_matched_by_request_id() {
+        let mut commands = MediaCommands::new();
+        commands.command(0x1234, MediaControlCommand::Pause, 0);
+
+        let unknown = MediaCommandResultData { request_id: 7, result: 0 };
+        assert_eq!(commands.handle_result(&unknown), None);
+
+        let result = MediaCommandResultData { request_id: 1, result: 0 };
+        assert_eq!(commands.handle_result(&result), Some(MediaControlCommand::Pause));
+        assert!(!commands.is_pending(1));
+    }
+}
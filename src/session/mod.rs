@@ -0,0 +1,697 @@
+pub mod ack;
+pub mod channel;
+pub mod console;
+pub mod fragment;
+pub mod input;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod media;
+pub mod sensor;
+pub mod text;
+pub mod touch;
+pub mod wait;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::constants;
+use crate::packet::{Packet, ReadError, WriteError, Type};
+use crate::packet::factory;
+use crate::packet::message::*;
+use crate::sgcrypto;
+use crate::sgcrypto::Crypto;
+use crate::state::*;
+use crate::util::{self, PublicKey};
+use crate::session::ack::AckTracker;
+use crate::session::channel::{ChannelManager, ServiceChannel};
+use crate::session::console::{ConsoleEvent, ConsoleState};
+use crate::session::fragment::FragmentAssembler;
+use crate::session::input::Gamepad;
+#[cfg(feature = "json")]
+use crate::session::json::JsonAssembler;
+use crate::session::media::MediaCommands;
+use crate::session::text::{SystemText, TitleText};
+use crate::session::touch::TouchTarget;
+
+use protocol::Parcel;
+
+/// How many console events are kept for `Session::poll_event`, older ones are dropped
+pub const MAX_EVENTS: usize = 64;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum SessionError {
+        ChannelFailed(service: ServiceChannel, result: u32) {
+            display("Opening a channel for {:?} failed with result {}", service, result)
+        }
+        ChannelNotOpen(service: ServiceChannel) {
+            display("No channel open for {:?}", service)
+        }
+        ConnectRejected(result: u16) {
+            display("Connect request rejected with result {}", result)
+        }
+        Crypto(err: sgcrypto::Error) { from() }
+        NoTextSession {
+            display("Neither the console nor a title asked for text")
+        }
+        Read(err: ReadError) { from() }
+        Rejected(sequence_numbers: Vec<u32>) {
+            display("Console rejected messages {:?}", sequence_numbers)
+        }
+        State(err: InvalidState) { from() }
+        TextTooLong(len: usize, max: u32) {
+            display("Text of {} characters exceeds the keyboard's limit of {}", len, max)
+        }
+        Unacknowledged(sequence_number: u32) {
+            display("Console never acknowledged message {}", sequence_number)
+        }
+        UnexpectedPacket(pkt_type: Type) {
+            display("Unexpected packet: {:?}", pkt_type)
+        }
+        Write(err: WriteError) { from() }
+    }
+}
+
+/// The protocol side of a SmartGlass session
+///
+/// A `Session` doesn't own a socket. It builds the packets that need to be
+/// sent and interprets the ones that were received, so the same logic can be
+/// driven by the blocking `Client` as well as by other transports.
+///
+/// Acknowledgements for received messages and retransmissions of our own
+/// unacknowledged messages are queued internally, transports are expected
+/// to drain them with `poll_transmit` whenever they send or receive, and
+/// again once `poll_timeout` has passed.
+pub struct Session {
+    state: SGState,
+    sequence_number: u32,
+    acks: AckTracker,
+    channels: ChannelManager,
+    fragments: FragmentAssembler,
+    #[cfg(feature = "json")]
+    json: JsonAssembler,
+    gamepad: Gamepad,
+    media: MediaCommands,
+    text: SystemText,
+    title_text: TitleText,
+    console: ConsoleState,
+    events: VecDeque<ConsoleEvent>,
+    outbox: VecDeque<Vec<u8>>
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            state: SGState::Disconnected,
+            sequence_number: 0,
+            acks: AckTracker::new(),
+            channels: ChannelManager::new(),
+            fragments: FragmentAssembler::new(),
+            #[cfg(feature = "json")]
+            json: JsonAssembler::new(),
+            gamepad: Gamepad::new(),
+            media: MediaCommands::new(),
+            text: SystemText::new(),
+            title_text: TitleText::new(),
+            console: ConsoleState::new(),
+            events: VecDeque::new(),
+            outbox: VecDeque::new()
+        }
+    }
+
+    pub fn state(&self) -> &SGState {
+        &self.state
+    }
+
+    pub fn channels(&self) -> &ChannelManager {
+        &self.channels
+    }
+
+    pub fn media(&self) -> &MediaCommands {
+        &self.media
+    }
+
+    pub fn text(&self) -> &SystemText {
+        &self.text
+    }
+
+    pub fn title_text(&self) -> &TitleText {
+        &self.title_text
+    }
+
+    /// What we know about the console from the messages it sent us
+    pub fn console(&self) -> &ConsoleState {
+        &self.console
+    }
+
+    /// The oldest change to the console that wasn't polled yet
+    ///
+    /// Only the last `MAX_EVENTS` events are kept.
+    pub fn poll_event(&mut self) -> Option<ConsoleEvent> {
+        self.events.pop_front()
+    }
+
+    /// Drops any connection state, returning the session to `SGState::Disconnected`
+    pub fn reset(&mut self) {
+        self.state = SGState::Disconnected;
+        self.sequence_number = 0;
+        self.acks = AckTracker::new();
+        self.channels = ChannelManager::new();
+        self.fragments = FragmentAssembler::new();
+        #[cfg(feature = "json")]
+        {
+            self.json = JsonAssembler::new();
+        }
+        self.gamepad = Gamepad::new();
+        self.media = MediaCommands::new();
+        self.text = SystemText::new();
+        self.title_text = TitleText::new();
+        self.console = ConsoleState::new();
+        self.events.clear();
+        self.outbox.clear();
+    }
+
+    /// Decodes a datagram received from the console
+    pub fn read(&self, data: &[u8]) -> Result<Packet, SessionError> {
+        Ok(Packet::read(data, &self.state)?)
+    }
+
+    /// Decodes a datagram received from the console and does the acknowledgement bookkeeping
+    ///
+    /// Messages that asked for it get an acknowledgement queued, acknowledgements
+    /// from the console settle our pending messages. Fragments are buffered until
+    /// the whole message arrived, which is then returned as if it had been sent
+    /// in one piece. With the `json` feature the same goes for the JSON
+    /// fragments of long `Json` messages.
+    ///
+    /// Returns `None` for messages that were already received before and for
+    /// fragments of incomplete messages.
+    ///
+    /// Channel responses and the console closing channels update `channels`.
+    pub fn receive(&mut self, data: &[u8]) -> Result<Option<Packet>, SessionError> {
+        let (header, message) = match self.read(data)? {
+            Packet::Message(header, message) => (header, message),
+            other => return Ok(Some(other))
+        };
+
+        let is_new = self.acks.receive(header.sequence_number);
+
+        if header.flags.need_ack {
+            let ack = Message::Acknowledge(self.acks.acknowledgement(header.sequence_number));
+            let ack = self.message(ack, constants::channel::ACK, false)?;
+            let data = self.raw_bytes(&ack)?;
+            self.outbox.push_back(data);
+        }
+
+        if !is_new {
+            return Ok(None);
+        }
+
+        self.dispatch(header, message)
+    }
+
+    /// Hands a new message to whatever keeps track of it
+    ///
+    /// Reassembled fragments go through here again as the message they make up.
+    fn dispatch(&mut self, header: MessageHeader, message: Message) -> Result<Option<Packet>, SessionError> {
+        match message {
+            Message::Acknowledge(ref data) => {
+                let rejected = self.acks.acknowledge(data);
+                if !rejected.is_empty() {
+                    return Err(SessionError::Rejected(rejected));
+                }
+            },
+            Message::StartChannelResponse(ref data) => {
+                self.channels.handle_response(data);
+            },
+            Message::StopChannel(ref data) => {
+                self.channels.handle_stop(data);
+            },
+            Message::MediaCommandResult(ref data) => {
+                self.media.handle_result(data);
+            },
+            Message::TitleTextConfiguration(ref data) => {
+                self.title_text.handle_configuration(header.channel_id, data);
+            },
+            Message::TitleTextInput(ref data) => {
+                self.title_text.handle_input(data);
+            },
+            Message::TitleTextSelection(ref data) => {
+                self.title_text.handle_selection(data);
+            },
+            Message::SystemTextConfiguration(ref data) => {
+                self.text.handle_configuration(data);
+            },
+            Message::SystemTextInput(ref data) => {
+                if let Some(ack) = self.text.handle_input(data) {
+                    let ack = self.message(Message::SystemTextAcknowledge(ack), header.channel_id, false)?;
+                    let data = self.raw_bytes(&ack)?;
+                    self.outbox.push_back(data);
+                }
+            },
+            Message::SystemTextAcknowledge(ref data) => {
+                self.text.handle_acknowledge(data);
+            },
+            Message::SystemTextDone(ref data) => {
+                self.text.handle_done(data);
+            },
+            #[cfg(feature = "json")]
+            Message::Json(ref data) => {
+                let data = match self.json.add(data) {
+                    Some(data) => data,
+                    None => return Ok(None)
+                };
+                return Ok(Some(Packet::Message(header, Message::Json(data))));
+            },
+            Message::Fragment(msg_type, ref data) => {
+                let payload = match self.fragments.add(header.sequence_number, data) {
+                    Some(payload) => payload,
+                    None => return Ok(None)
+                };
+
+                let message = Message::read_payload(msg_type, &payload).map_err(ReadError::from)?;
+                let mut header = header;
+                header.flags.is_fragment = false;
+                header.protected_payload_length = payload.len() as u16;
+
+                return self.dispatch(header, message);
+            },
+            _ => {
+                self.update_console(&message);
+            }
+        }
+
+        Ok(Some(Packet::Message(header, message)))
+    }
+
+    /// Returns the next datagram that needs to go out
+    ///
+    /// These are queued acknowledgements first, then retransmissions that are
+    /// due at `now`. Fails once the console didn't acknowledge a message after
+    /// all retransmissions.
+    pub fn poll_transmit(&mut self, now: Instant) -> Result<Option<Vec<u8>>, SessionError> {
+        if let Some(data) = self.outbox.pop_front() {
+            return Ok(Some(data));
+        }
+
+        self.acks.poll_retransmit(now).map_err(SessionError::Unacknowledged)
+    }
+
+    /// The next time `poll_transmit` needs to be called for retransmissions
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.acks.next_timeout()
+    }
+
+    fn update_console(&mut self, message: &Message) {
+        for event in self.console.apply(message) {
+            if self.events.len() == MAX_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+    }
+
+    /// Serializes a packet for sending, encrypting and signing it if the session requires it
+    pub fn raw_bytes(&self, packet: &Packet) -> Result<Vec<u8>, SessionError> {
+        Ok(packet.raw_bytes(&self.state)?)
+    }
+
+    /// Builds the connect requests and moves the session into the connecting state
+    ///
+    /// Long credentials are split over a group of requests that need to be sent
+    /// in order, the console answers the last one with its connect response.
+    ///
+    /// # Arguments
+    /// * crypto - the crypto context negotiated with the console's public key
+    /// * sg_uuid - the UUID identifying this client
+    /// * userhash - the Xbox Live userhash, may be empty for anonymous connections
+    /// * jwt - the XSTS token, may be empty for anonymous connections
+    pub fn connect_request(&mut self, crypto: Crypto, sg_uuid: Uuid, userhash: String, jwt: String) -> Result<Vec<Packet>, SessionError> {
+        self.state.ensure_disconnected()?;
+
+        let public_key = PublicKey::new(crypto.key_type(), crypto.public_key().to_vec())?;
+        let packets = factory::connect_request_group(sg_uuid, public_key, userhash, jwt, 0, || {
+            let mut iv = [0u8; 16];
+            sgcrypto::random_bytes(&mut iv)?;
+            Ok::<_, SessionError>(iv)
+        })?;
+
+        self.state = SGState::Connected(State {
+            connection_state: ConnectionState::Connecting,
+            pairing_state: PairingState::NotPaired,
+            participant_id: 0,
+            crypto
+        });
+
+        Ok(packets)
+    }
+
+    /// Completes the handshake with the console's connect response
+    ///
+    /// Returns `false` if the console answered a request before the last of
+    /// the group and the handshake isn't done yet.
+    pub fn handle_connect_response(&mut self, packet: &Packet) -> Result<bool, SessionError> {
+        let data = match *packet {
+            Packet::ConnectResponse(_, _, ref data) => data,
+            ref other => return Err(SessionError::UnexpectedPacket(other.pkt_type()))
+        };
+
+        if data.connect_request == constants::connect_result::PENDING {
+            return Ok(false);
+        }
+        if data.connect_request != constants::connect_result::SUCCESS {
+            self.reset();
+            return Err(SessionError::ConnectRejected(data.connect_request));
+        }
+
+        let state = self.state.ensure_connected_mut()?;
+        state.connection_state = ConnectionState::Connected;
+        state.pairing_state = PairingState::from_raw(data.pairing_state);
+        state.participant_id = data.participant_id;
+
+        Ok(true)
+    }
+
+    /// Wraps a message in a header addressed to the console, assigning it the next sequence number
+    ///
+    /// # Arguments
+    /// * message - the message to be sent
+    /// * channel_id - the channel the message belongs to
+    /// * need_ack - whether the console should acknowledge the message
+    pub fn message(&mut self, message: Message, channel_id: u64, need_ack: bool) -> Result<Packet, SessionError> {
+        let participant_id = self.state.ensure_connected()?.participant_id;
+        self.sequence_number += 1;
+
+        let header = MessageHeader {
+            pkt_type: Type::Message,
+            protected_payload_length: 0,
+            sequence_number: self.sequence_number,
+            target_participant_id: 0,
+            source_participant_id: participant_id,
+            flags: MessageHeaderFlags {
+                msg_type: message.msg_type(),
+                need_ack,
+                is_fragment: message.is_fragment(),
+                version: 2
+            },
+            channel_id
+        };
+
+        Ok(Packet::Message(header, message))
+    }
+
+    /// Builds and serializes a message, keeping it for retransmission if it needs an acknowledgement
+    ///
+    /// Messages larger than `fragment::MAX_PAYLOAD_LEN` are split into
+    /// fragments, so this returns one datagram per fragment.
+    ///
+    /// # Arguments
+    /// * message - the message to be sent
+    /// * channel_id - the channel the message belongs to
+    /// * need_ack - whether the console should acknowledge the message
+    /// * now - the time the message is sent
+    pub fn send_message(&mut self, message: Message, channel_id: u64, need_ack: bool, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let payload = message.raw_bytes().map_err(WriteError::from)?;
+        if payload.len() <= fragment::MAX_PAYLOAD_LEN {
+            return Ok(vec![self.send_single(message, channel_id, need_ack, now)?]);
+        }
+
+        let msg_type = message.msg_type();
+        fragment::split(&payload, self.sequence_number + 1).into_iter()
+            .map(|data| self.send_single(Message::Fragment(msg_type, data), channel_id, need_ack, now))
+            .collect()
+    }
+
+    /// Sends a message on the channel opened for `service`
+    pub fn send_on(&mut self, service: ServiceChannel, message: Message, need_ack: bool, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.channels.channel_id(service).ok_or(SessionError::ChannelNotOpen(service))?;
+        self.send_message(message, channel_id, need_ack, now)
+    }
+
+    /// Asks the console to open a channel for `service`
+    ///
+    /// Returns the id of the request, the console's `StartChannelResponse` will carry it.
+    pub fn start_channel(&mut self, service: ServiceChannel, now: Instant) -> Result<(u32, Vec<Vec<u8>>), SessionError> {
+        self.state.ensure_connected()?;
+        let request = self.channels.start(service);
+        let request_id = request.channel_request_id;
+        let datagrams = self.send_message(Message::StartChannelRequest(request), constants::channel::CORE, true, now)?;
+        Ok((request_id, datagrams))
+    }
+
+    /// Closes the channel for `service`
+    pub fn stop_channel(&mut self, service: ServiceChannel, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        self.state.ensure_connected()?;
+        let stop = self.channels.stop(service).ok_or(SessionError::ChannelNotOpen(service))?;
+        self.send_message(Message::StopChannel(stop), constants::channel::CORE, true, now)
+    }
+
+    /// Holds `buttons` down on the gamepad until they are released
+    ///
+    /// # Arguments
+    /// * buttons - the buttons to hold in addition to the ones already held
+    /// * timestamp - the time of the report in milliseconds, see `input::timestamp`
+    /// * now - the time the message is sent
+    pub fn hold(&mut self, buttons: GamepadButtons, timestamp: u64, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.input_channel()?;
+        let report = self.gamepad.hold(buttons, timestamp);
+        self.send_message(Message::Gamepad(report), channel_id, false, now)
+    }
+
+    /// Lets go of gamepad `buttons` that were held
+    pub fn release(&mut self, buttons: GamepadButtons, timestamp: u64, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.input_channel()?;
+        let report = self.gamepad.release(buttons, timestamp);
+        self.send_message(Message::Gamepad(report), channel_id, false, now)
+    }
+
+    /// Presses and releases gamepad `buttons`, returning the datagrams of both reports
+    pub fn press(&mut self, buttons: GamepadButtons, timestamp: u64, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.input_channel()?;
+        let [down, up] = self.gamepad.press(buttons, timestamp);
+
+        let mut datagrams = self.send_message(Message::Gamepad(down), channel_id, false, now)?;
+        datagrams.extend(self.send_message(Message::Gamepad(up), channel_id, false, now)?);
+        Ok(datagrams)
+    }
+
+    /// Asks the console to launch a title, see `TitleLaunchData` for building the request
+    pub fn launch_title(&mut self, launch: TitleLaunchData, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        self.send_message(Message::TitleLaunch(launch), constants::channel::CORE, true, now)
+    }
+
+    /// Sends a sensor reading to the title `title_id`, see `sensor::SensorStream` for building them
+    pub fn sensor(&mut self, title_id: u32, message: Message, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        self.send_on(ServiceChannel::Title(title_id), message, false, now)
+    }
+
+    /// Sends a single touch report, see `touch::Gesture` for building them
+    ///
+    /// Like gamepad input, reports aren't acknowledged, a retransmitted move
+    /// would only arrive after the finger went on already.
+    pub fn touch(&mut self, report: TouchData, target: TouchTarget, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        match target {
+            TouchTarget::System => {
+                let channel_id = self.input_channel()?;
+                self.send_message(Message::SystemTouch(report), channel_id, false, now)
+            },
+            TouchTarget::Title(title_id) => {
+                self.send_on(ServiceChannel::Title(title_id), Message::TitleTouch(report), false, now)
+            }
+        }
+    }
+
+    /// Sends a media `command` to the title `title_id`
+    ///
+    /// Returns the request id the console's `MediaCommandResult` will carry.
+    pub fn media_command(&mut self, title_id: u32, command: MediaControlCommand, now: Instant) -> Result<(u64, Vec<Vec<u8>>), SessionError> {
+        self.send_media_command(title_id, command, 0, now)
+    }
+
+    /// Seeks the media playing in `title_id` to `position`
+    ///
+    /// Returns the request id the console's `MediaCommandResult` will carry.
+    pub fn media_seek(&mut self, title_id: u32, position: Duration, now: Instant) -> Result<(u64, Vec<Vec<u8>>), SessionError> {
+        self.send_media_command(title_id, MediaControlCommand::Seek, util::duration_to_ticks(position), now)
+    }
+
+    fn send_media_command(&mut self, title_id: u32, command: MediaControlCommand, seek_position: u64, now: Instant) -> Result<(u64, Vec<Vec<u8>>), SessionError> {
+        let channel_id = self.channels.channel_id(ServiceChannel::SystemMedia)
+            .ok_or(SessionError::ChannelNotOpen(ServiceChannel::SystemMedia))?;
+        let data = self.media.command(title_id, command, seek_position);
+        let request_id = data.request_id;
+        let datagrams = self.send_message(Message::MediaCommand(data), channel_id, true, now)?;
+        Ok((request_id, datagrams))
+    }
+
+    /// Replaces the text of the on-screen keyboard the console opened
+    ///
+    /// Returns the version the console's `SystemTextAcknowledge` will carry.
+    pub fn submit_text(&mut self, text: &str, now: Instant) -> Result<(u32, Vec<Vec<u8>>), SessionError> {
+        let channel_id = self.channels.channel_id(ServiceChannel::SystemText)
+            .ok_or(SessionError::ChannelNotOpen(ServiceChannel::SystemText))?;
+        let max_text_len = self.text.prompt().ok_or(SessionError::NoTextSession)?.max_text_len;
+        let len = text.chars().count();
+        if max_text_len != 0 && len > max_text_len as usize {
+            return Err(SessionError::TextTooLong(len, max_text_len));
+        }
+
+        let mut version = 0;
+        let mut datagrams = Vec::new();
+        for chunk in self.text.submit(text).ok_or(SessionError::NoTextSession)? {
+            version = chunk.submitted_version;
+            datagrams.extend(self.send_message(Message::SystemTextInput(chunk), channel_id, true, now)?);
+        }
+        Ok((version, datagrams))
+    }
+
+    /// Closes the on-screen keyboard, accepting or cancelling the text
+    pub fn finish_text(&mut self, result: TextResult, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.channels.channel_id(ServiceChannel::SystemText)
+            .ok_or(SessionError::ChannelNotOpen(ServiceChannel::SystemText))?;
+        let done = self.text.done(result).ok_or(SessionError::NoTextSession)?;
+        self.send_message(Message::SystemTextDone(done), channel_id, true, now)
+    }
+
+    /// Answers the title that asked for text with `text`
+    pub fn title_text_input(&mut self, text: &str, result: TextResult, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.title_text.channel_id().ok_or(SessionError::NoTextSession)?;
+        let input = self.title_text.input(text, result).ok_or(SessionError::NoTextSession)?;
+        self.send_message(Message::TitleTextInput(input), channel_id, true, now)
+    }
+
+    /// Selects `length` bytes of the title's text starting at `start`
+    pub fn title_text_select(&mut self, start: u32, length: u32, now: Instant) -> Result<Vec<Vec<u8>>, SessionError> {
+        let channel_id = self.title_text.channel_id().ok_or(SessionError::NoTextSession)?;
+        let selection = self.title_text.select(start, length).ok_or(SessionError::NoTextSession)?;
+        self.send_message(Message::TitleTextSelection(selection), channel_id, true, now)
+    }
+
+    /// Stops tracking the title's text session
+    pub fn close_title_text(&mut self) {
+        self.title_text.close();
+    }
+
+    fn input_channel(&self) -> Result<u64, SessionError> {
+        self.channels.channel_id(ServiceChannel::SystemInput)
+            .ok_or(SessionError::ChannelNotOpen(ServiceChannel::SystemInput))
+    }
+
+    fn send_single(&mut self, message: Message, channel_id: u64, need_ack: bool, now: Instant) -> Result<Vec<u8>, SessionError> {
+        let packet = self.message(message, channel_id, need_ack)?;
+        let data = self.raw_bytes(&packet)?;
+
+        if need_ack {
+            self.acks.track(self.sequence_number, data.clone(), now);
+        }
+
+        Ok(data)
+    }
+
+    /// Builds the message telling the console we're going away
+    ///
+    /// The session stays connected so the packet can still be serialized,
+    /// call `reset` once it has been sent.
+    pub fn disconnect(&mut self) -> Result<Packet, SessionError> {
+        let message = Message::Disconnect(DisconnectData {
+            reason: 0,
+            error_code: 0
+        });
+
+        self.message(message, constants::channel::CORE, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::simple::{ConnectResponseProtectedData, ConnectResponseUnprotectedData, SimpleHeader};
+
+    fn test_crypto() -> Crypto {
+        let secret = sgcrypto::SharedSecret::from_bytes(util::PublicKeyType::EcDhP256, include_bytes!("../test/secret")).unwrap();
+        Crypto::from_secret(&secret)
+    }
+
+    fn connected_session() -> Session {
+        let mut session = Session::new();
+        let crypto = test_crypto();
+        session.connect_request(crypto, Uuid::nil(), String::new(), String::new()).unwrap();
+        session
+    }
+
+    #[test]
+    fn connect_request_moves_to_connecting() {
+        let session = connected_session();
+        let state = session.state().ensure_connected().unwrap();
+        assert_eq!(state.connection_state, ConnectionState::Connecting);
+    }
+
+    #[test]
+    fn connect_request_requires_disconnected() {
+        let mut session = connected_session();
+        let crypto = test_crypto();
+        assert!(session.connect_request(crypto, Uuid::nil(), String::new(), String::new()).is_err());
+    }
+
+    #[test]
+    fn connect_request_carries_credentials() {
+        let mut session = Session::new();
+        let crypto = test_crypto();
+        let request = session.connect_request(crypto, Uuid::nil(), String::from("userhash"), String::from("jwt")).unwrap();
+        assert_eq!(request.len(), 1);
+        let data = session.raw_bytes(&request[0]).unwrap();
+
+        match session.read(&data).unwrap() {
+            Packet::ConnectRequest(_, _, protected_data) => {
+                assert_eq!(protected_data.userhash.to_str(), "userhash");
+                assert_eq!(protected_data.jwt.to_str(), "jwt");
+            },
+            _ => panic!("Wrong type")
+        }
+    }
+
+    fn connect_response(result: u16) -> Packet {
+        Packet::ConnectResponse(
+            SimpleHeader::new(Type::ConnectResponse, 2),
+            ConnectResponseUnprotectedData { iv: [0u8; 16] },
+            ConnectResponseProtectedData { connect_request: result, pairing_state: 0, participant_id: 31 }
+        )
+    }
+
+    #[test]
+    fn pending_connect_response_keeps_connecting() {
+        let mut session = connected_session();
+
+        assert!(!session.handle_connect_response(&connect_response(constants::connect_result::PENDING)).unwrap());
+        assert_eq!(session.state().ensure_connected().unwrap().connection_state, ConnectionState::Connecting);
+
+        assert!(session.handle_connect_response(&connect_response(constants::connect_result::SUCCESS)).unwrap());
+        let state = session.state().ensure_connected().unwrap();
+        assert_eq!(state.connection_state, ConnectionState::Connected);
+        assert_eq!(state.participant_id, 31);
+    }
+
+    #[test]
+    fn message_assigns_sequence_numbers() {
+        let mut session = connected_session();
+        let first = session.disconnect().unwrap();
+        let second = session.disconnect().unwrap();
+
+        match (first, second) {
+            (Packet::Message(first, _), Packet::Message(second, _)) => {
+                assert_eq!(first.sequence_number, 1);
+                assert_eq!(second.sequence_number, 2);
+                assert_eq!(first.flags.msg_type, MessageType::Disconnect);
+            },
+            _ => panic!("Wrong type")
+        }
+    }
+
+    #[test]
+    fn message_requires_connection() {
+        let mut session = Session::new();
+        assert!(session.disconnect().is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::packet::message::*;
+
+/// Where a `SensorStream` gets the current time from
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when it is told to, for tests
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Cell<Instant>
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            now: Cell::new(Instant::now())
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+impl<'a, C: Clock> Clock for &'a C {
+    fn now(&self) -> Instant {
+        (*self).now()
+    }
+}
+
+/// A single reading of one of the sensors a title can ask for
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorSample {
+    /// Acceleration along the three axes in g
+    Accelerometer { x: f32, y: f32, z: f32 },
+    /// Angular velocity around the three axes in degrees per second
+    Gyrometer { x: f32, y: f32, z: f32 },
+    /// The tilt of the device in degrees
+    Inclinometer { pitch: f32, roll: f32, yaw: f32 },
+    /// The heading of the device in degrees
+    Compass { magnetic_north: f32, true_north: f32 },
+    /// The rotation of the device as a quaternion
+    Orientation { rotation_matrix_value: u64, w: f32, x: f32, y: f32, z: f32 }
+}
+
+impl SensorSample {
+    /// Wraps the sample in the message for its sensor
+    pub fn into_message(self, timestamp: u64) -> Message {
+        match self {
+            SensorSample::Accelerometer { x, y, z } => Message::Accelerometer(AccelerometerData {
+                timestamp,
+                acceleration_x: x,
+                acceleration_y: y,
+                acceleration_z: z
+            }),
+            SensorSample::Gyrometer { x, y, z } => Message::Gyrometer(GyrometerData {
+                timestamp,
+                angular_velocity_x: x,
+                angular_velocity_y: y,
+                angular_velocity_z: z
+            }),
+            SensorSample::Inclinometer { pitch, roll, yaw } => Message::Inclinometer(InclinometerData {
+                timestamp,
+                pitch,
+                roll,
+                yaw
+            }),
+            SensorSample::Compass { magnetic_north, true_north } => Message::Compass(CompassData {
+                timestamp,
+                magnetic_north,
+                true_north
+            }),
+            SensorSample::Orientation { rotation_matrix_value, w, x, y, z } => Message::Orientation(OrientationData {
+                timestamp,
+                rotation_matrix_value,
+                w,
+                x,
+                y,
+                z
+            })
+        }
+    }
+}
+
+/// Paces sensor samples and gives them timestamps
+///
+/// Timestamps are microseconds since the stream was created, taken from a
+/// monotonic clock and bumped if necessary so they always increase. At most
+/// `rate` samples per second are let through.
+pub struct SensorStream<C: Clock = SystemClock> {
+    clock: C,
+    start: Instant,
+    interval: Duration,
+    next_due: Option<Instant>,
+    last_timestamp: Option<u64>
+}
+
+impl SensorStream<SystemClock> {
+    /// A stream sending at most `rate` samples per second
+    pub fn new(rate: u32) -> Self {
+        SensorStream::with_clock(rate, SystemClock)
+    }
+}
+
+impl<C: Clock> SensorStream<C> {
+    pub fn with_clock(rate: u32, clock: C) -> Self {
+        let start = clock.now();
+
+        SensorStream {
+            clock,
+            start,
+            interval: Duration::from_secs(1) / rate.max(1),
+            next_due: None,
+            last_timestamp: None
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// The time between two samples
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// How long to wait until the next sample may be sent
+    pub fn delay(&self) -> Duration {
+        self.next_due.map_or(Duration::from_secs(0), |due| due.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Builds the message for `sample` if it is due
+    ///
+    /// Returns `None` if the last sample went out less than an interval ago,
+    /// the sample should be dropped or retried after `delay`.
+    pub fn stamp(&mut self, sample: SensorSample) -> Option<Message> {
+        let now = self.clock.now();
+        if self.next_due.map_or(false, |due| now < due) {
+            return None;
+        }
+
+        // Catch up from the current time instead of bursting after a pause
+        let next_due = self.next_due.map_or(now, |due| due + self.interval);
+        self.next_due = Some(if next_due > now { next_due } else { now + self.interval });
+
+        let elapsed = now.duration_since(self.start).as_micros() as u64;
+        let timestamp = match self.last_timestamp {
+            Some(last) if elapsed <= last => last + 1,
+            _ => elapsed
+        };
+        self.last_timestamp = Some(timestamp);
+
+        Some(sample.into_message(timestamp))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> SensorSample {
+        SensorSample::Accelerometer { x: 0.0, y: 0.0, z: 1.0 }
+    }
+
+    fn timestamp(message: Message) -> u64 {
+        match message {
+            Message::Accelerometer(data) => data.timestamp,
+            _ => panic!("Wrong type")
+        }
+    }
+
+    #[test]
+    fn rate_is_limited() {
+        let clock = ManualClock::new();
+        let mut stream = SensorStream::with_clock(50, &clock);
+        assert_eq!(stream.interval(), Duration::from_millis(20));
+
+        assert!(stream.stamp(sample()).is_some());
+        assert!(stream.stamp(sample()).is_none());
+        assert_eq!(stream.delay(), Duration::from_millis(20));
+
+        clock.advance(Duration::from_millis(15));
+        assert!(stream.stamp(sample()).is_none());
+        assert_eq!(stream.delay(), Duration::from_millis(5));
+
+        clock.advance(Duration::from_millis(5));
+        assert!(stream.stamp(sample()).is_some());
+    }
+
+    #[test]
+    fn timestamps_are_monotonic() {
+        let clock = ManualClock::new();
+        let mut stream = SensorStream::with_clock(1000, &clock);
+
+        assert_eq!(timestamp(stream.stamp(sample()).unwrap()), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(timestamp(stream.stamp(sample()).unwrap()), 1000);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(timestamp(stream.stamp(sample()).unwrap()), 251_000);
+    }
+
+    #[test]
+    fn pauses_do_not_burst() {
+        let clock = ManualClock::new();
+        let mut stream = SensorStream::with_clock(10, &clock);
+
+        stream.stamp(sample()).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert!(stream.stamp(sample()).is_some());
+        assert!(stream.stamp(sample()).is_none());
+    }
+
+    #[test]
+    fn samples_become_messages() {
+        let message = SensorSample::Compass { magnetic_north: 90.0, true_north: 92.5 }.into_message(7);
+        assert_eq!(message, Message::Compass(CompassData {
+            timestamp: 7,
+            magnetic_north: 90.0,
+            true_north: 92.5
+        }));
+    }
+}
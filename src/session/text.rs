@@ -0,0 +1,540 @@
+use std::collections::BTreeMap;
+
+use crate::packet::message::{SystemTextAcknowledgeData, SystemTextDoneData, SystemTextInputData, TextConfigurationData};
+use crate::packet::message::{TextDelta, TextResult, TitleTextInputData, TitleTextSelectionData};
+use crate::util::SGString;
+
+/// The most text bytes sent in a single `SystemTextInput` message
+pub const MAX_CHUNK_LEN: usize = 512;
+/// Sent as `selection_start` and `selection_end` to leave the selection alone
+const NO_SELECTION: u32 = 0xffff_ffff;
+
+/// An on-screen keyboard the console or a title opened
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextPrompt {
+    pub session_id: u64,
+    pub options: u32,
+    pub input_scope: u32,
+    /// The longest text the keyboard accepts in characters, 0 if there is no limit
+    pub max_text_len: u32,
+    pub locale: String,
+    pub prompt: String
+}
+
+impl TextPrompt {
+    pub fn from_configuration(config: &TextConfigurationData) -> Self {
+        TextPrompt {
+            session_id: config.session_id,
+            options: config.options,
+            input_scope: config.input_scope,
+            max_text_len: config.max_text_len,
+            locale: config.locale.to_str(),
+            prompt: config.prompt.to_str()
+        }
+    }
+}
+
+struct TextSession {
+    prompt: TextPrompt,
+    version: u32,
+    text: String,
+    /// The version of our last submission until the console acknowledges it
+    submitted_version: Option<u32>,
+    /// The version the console is sending us in chunks
+    incoming_version: u32,
+    /// The chunks of `incoming_version` that arrived so far by their byte offset
+    incoming: BTreeMap<usize, Vec<u8>>
+}
+
+/// Keeps track of the on-screen keyboard of the console
+///
+/// The console opens a text session with a `SystemTextConfiguration`. Both
+/// sides then send the complete text as `SystemTextInput`, split into chunks
+/// at `text_chunk_byte_start`, on top of the version they last saw. Every
+/// submission is confirmed with a `SystemTextAcknowledge` for its version,
+/// and the session ends with a `SystemTextDone` from either side.
+pub struct SystemText {
+    session: Option<TextSession>
+}
+
+impl SystemText {
+    pub fn new() -> Self {
+        SystemText {
+            session: None
+        }
+    }
+
+    /// The keyboard that is currently open, if any
+    pub fn prompt(&self) -> Option<&TextPrompt> {
+        self.session.as_ref().map(|session| &session.prompt)
+    }
+
+    /// The text as last agreed upon with the console
+    pub fn text(&self) -> Option<&str> {
+        self.session.as_ref().map(|session| session.text.as_str())
+    }
+
+    /// The text version as last agreed upon with the console
+    pub fn version(&self) -> Option<u32> {
+        self.session.as_ref().map(|session| session.version)
+    }
+
+    /// Whether a submission still waits for its acknowledgement
+    pub fn is_pending(&self) -> bool {
+        self.session.as_ref().map_or(false, |session| session.submitted_version.is_some())
+    }
+
+    /// Starts a text session, replacing whatever session was open
+    pub fn handle_configuration(&mut self, config: &TextConfigurationData) -> &TextPrompt {
+        self.session = Some(TextSession {
+            prompt: TextPrompt::from_configuration(config),
+            version: config.buffer_version,
+            text: String::new(),
+            submitted_version: None,
+            incoming_version: config.buffer_version,
+            incoming: BTreeMap::new()
+        });
+
+        &self.session.as_ref().unwrap().prompt
+    }
+
+    /// Applies text the console sent us
+    ///
+    /// Edits in the delta of the message are applied to the current text,
+    /// otherwise the text is collected from its chunks, which are placed at
+    /// their byte offset so they may arrive in any order or repeatedly.
+    /// Returns the acknowledgement to send back once the new text is complete.
+    pub fn handle_input(&mut self, input: &SystemTextInputData) -> Option<SystemTextAcknowledgeData> {
+        let session = self.session.as_mut().filter(|session| session.prompt.session_id == u64::from(input.session_id))?;
+
+        if input.apply_delta(&mut session.text) {
+            session.version = input.submitted_version;
+            return Some(SystemTextAcknowledgeData {
+                session_id: input.session_id,
+                version_ack: input.submitted_version
+            });
+        }
+
+        if input.submitted_version != session.incoming_version {
+            session.incoming.clear();
+            session.incoming_version = input.submitted_version;
+        }
+        session.incoming.insert(input.text_chunk_byte_start as usize, input.text_chunk.value().as_bytes().to_vec());
+
+        let text = assemble(&session.incoming, input.total_text_byte_len as usize)?;
+        session.text = String::from_utf8_lossy(&text).into_owned();
+        session.incoming.clear();
+        session.version = input.submitted_version;
+
+        Some(SystemTextAcknowledgeData {
+            session_id: input.session_id,
+            version_ack: input.submitted_version
+        })
+    }
+
+    /// Applies the console's acknowledgement of a submission
+    ///
+    /// Returns whether it acknowledged our latest submission.
+    pub fn handle_acknowledge(&mut self, ack: &SystemTextAcknowledgeData) -> bool {
+        let session = match self.session.as_mut() {
+            Some(session) if session.prompt.session_id == u64::from(ack.session_id) => session,
+            _ => return false
+        };
+
+        if session.submitted_version != Some(ack.version_ack) {
+            return false;
+        }
+
+        session.submitted_version = None;
+        session.version = ack.version_ack;
+        true
+    }
+
+    /// Ends the text session after the console closed the keyboard
+    pub fn handle_done(&mut self, done: &SystemTextDoneData) {
+        if self.session.as_ref().map_or(false, |session| session.prompt.session_id == u64::from(done.session_id)) {
+            self.session = None;
+        }
+    }
+
+    /// Builds the messages replacing the text with `text`
+    ///
+    /// Returns `None` without an open keyboard. The console acknowledges the
+    /// submission with the `submitted_version` of the messages.
+    pub fn submit(&mut self, text: &str) -> Option<Vec<SystemTextInputData>> {
+        let session = self.session.as_mut()?;
+        // System text sessions only use the low 32 bits of the id
+        let session_id = session.prompt.session_id as u32;
+        let base_version = session.version;
+        let submitted_version = base_version + 1;
+
+        let chunks = chunks(text).into_iter()
+            .map(|(start, chunk)| SystemTextInputData {
+                session_id,
+                base_version,
+                submitted_version,
+                total_text_byte_len: text.len() as u32,
+                selection_start: NO_SELECTION,
+                selection_end: NO_SELECTION,
+                flags: 0,
+                text_chunk_byte_start: start as u32,
+                text_chunk: SGString::from_str(chunk.to_string()),
+                delta: None
+            })
+            .collect();
+
+        session.text = text.to_string();
+        session.submitted_version = Some(submitted_version);
+
+        Some(chunks)
+    }
+
+    /// Builds the message closing the keyboard and ends the session
+    ///
+    /// `TextResult::Accept` keeps the text, `TextResult::Cancel` throws it away.
+    pub fn done(&mut self, result: TextResult) -> Option<SystemTextDoneData> {
+        let session = self.session.take()?;
+
+        Some(SystemTextDoneData {
+            session_id: session.prompt.session_id as u32,
+            version: session.version,
+            flags: u32::from(result.raw()),
+            unk: 0
+        })
+    }
+}
+
+struct TitleTextSession {
+    prompt: TextPrompt,
+    channel_id: u64,
+    buffer_version: u32,
+    text: String,
+    selection: (u32, u32)
+}
+
+/// Keeps track of text input a title asked for
+///
+/// Titles open their own text sessions with a `TitleTextConfiguration`
+/// instead of using the system keyboard. Every `TitleTextInput` and
+/// `TitleTextSelection` carries the buffer version it applies to, which
+/// increases with every change from either side. The session lasts until
+/// the title sends a new configuration or `close` is called.
+pub struct TitleText {
+    session: Option<TitleTextSession>
+}
+
+impl TitleText {
+    pub fn new() -> Self {
+        TitleText {
+            session: None
+        }
+    }
+
+    /// What the title asked for, if it is waiting for text
+    pub fn prompt(&self) -> Option<&TextPrompt> {
+        self.session.as_ref().map(|session| &session.prompt)
+    }
+
+    /// The channel the title talks to us on
+    pub fn channel_id(&self) -> Option<u64> {
+        self.session.as_ref().map(|session| session.channel_id)
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.session.as_ref().map(|session| session.text.as_str())
+    }
+
+    pub fn buffer_version(&self) -> Option<u32> {
+        self.session.as_ref().map(|session| session.buffer_version)
+    }
+
+    /// The selected range of the text as start and length
+    pub fn selection(&self) -> Option<(u32, u32)> {
+        self.session.as_ref().map(|session| session.selection)
+    }
+
+    /// Starts a text session for the title talking to us on `channel_id`
+    pub fn handle_configuration(&mut self, channel_id: u64, config: &TextConfigurationData) -> &TextPrompt {
+        self.session = Some(TitleTextSession {
+            prompt: TextPrompt::from_configuration(config),
+            channel_id,
+            buffer_version: config.buffer_version,
+            text: String::new(),
+            selection: (0, 0)
+        });
+
+        &self.session.as_ref().unwrap().prompt
+    }
+
+    /// Applies text the title sent us
+    ///
+    /// Updates for older buffer versions are ignored.
+    pub fn handle_input(&mut self, input: &TitleTextInputData) {
+        if let Some(session) = self.current(input.session_id, input.buffer_version) {
+            session.buffer_version = input.buffer_version;
+            session.text = input.text.to_str();
+        }
+    }
+
+    /// Applies a selection change the title sent us
+    pub fn handle_selection(&mut self, selection: &TitleTextSelectionData) {
+        if let Some(session) = self.current(selection.session_id, selection.buffer_version) {
+            session.buffer_version = selection.buffer_version;
+            session.selection = (selection.start, selection.length);
+        }
+    }
+
+    /// Builds the message replacing the text with `text`
+    ///
+    /// `TextResult::Accept` sends the text to the title, `TextResult::Cancel`
+    /// tells it the user backed out. Returns `None` if no title asked for text.
+    pub fn input(&mut self, text: &str, result: TextResult) -> Option<TitleTextInputData> {
+        let session = self.session.as_mut()?;
+        session.buffer_version += 1;
+        session.text = text.to_string();
+
+        Some(TitleTextInputData {
+            session_id: session.prompt.session_id,
+            buffer_version: session.buffer_version,
+            result,
+            text: SGString::from_str(text.to_string())
+        })
+    }
+
+    /// Builds the message selecting `length` bytes starting at `start`
+    pub fn select(&mut self, start: u32, length: u32) -> Option<TitleTextSelectionData> {
+        let session = self.session.as_mut()?;
+        session.buffer_version += 1;
+        session.selection = (start, length);
+
+        Some(TitleTextSelectionData {
+            session_id: session.prompt.session_id,
+            buffer_version: session.buffer_version,
+            start,
+            length
+        })
+    }
+
+    /// Forgets about the session once we're done with it
+    pub fn close(&mut self) {
+        self.session = None;
+    }
+
+    fn current(&mut self, session_id: u64, buffer_version: u32) -> Option<&mut TitleTextSession> {
+        self.session.as_mut()
+            .filter(|session| session.prompt.session_id == session_id && buffer_version >= session.buffer_version)
+    }
+}
+
+/// Joins the chunks of a text once they cover all of its `len` bytes
+fn assemble(chunks: &BTreeMap<usize, Vec<u8>>, len: usize) -> Option<Vec<u8>> {
+    let mut text = Vec::with_capacity(len);
+    for (&start, chunk) in chunks.iter() {
+        if start > text.len() {
+            return None;
+        }
+        let overlap = (text.len() - start).min(chunk.len());
+        text.extend_from_slice(&chunk[overlap..]);
+    }
+
+    if text.len() < len {
+        return None;
+    }
+    text.truncate(len);
+    Some(text)
+}
+
+/// Splits `text` into chunks of at most `MAX_CHUNK_LEN` bytes without breaking up characters
+///
+/// Returns the byte offset of every chunk along with it, empty text is sent as one empty chunk.
+fn chunks(text: &str) -> Vec<(usize, &str)> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    loop {
+        let mut end = (start + MAX_CHUNK_LEN).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push((start, &text[start..end]));
+
+        if end == text.len() {
+            return chunks;
+        }
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn configuration(max_text_len: u32) -> TextConfigurationData {
+        TextConfigurationData {
+            session_id: 9,
+            buffer_version: 3,
+            options: 5,
+            input_scope: 57,
+            max_text_len,
+            locale: SGString::from_str(String::from("de-DE")),
+            prompt: SGString::from_str(String::from("Search"))
+        }
+    }
+
+    #[test]
+    fn configuration_opens_session() {
+        let mut text = SystemText::new();
+        assert_eq!(text.prompt(), None);
+
+        let prompt = text.handle_configuration(&configuration(64)).clone();
+        assert_eq!(prompt.session_id, 9);
+        assert_eq!(prompt.prompt, "Search");
+        assert_eq!(prompt.max_text_len, 64);
+        assert_eq!(text.version(), Some(3));
+    }
+
+    #[test]
+    fn submit_works() {
+        let mut text = SystemText::new();
+        text.handle_configuration(&configuration(0));
+
+        let chunks = text.submit("hello").unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].base_version, 3);
+        assert_eq!(chunks[0].submitted_version, 4);
+        assert_eq!(chunks[0].total_text_byte_len, 5);
+        assert!(text.is_pending());
+
+        assert!(!text.handle_acknowledge(&SystemTextAcknowledgeData { session_id: 9, version_ack: 3 }));
+        assert!(text.handle_acknowledge(&SystemTextAcknowledgeData { session_id: 9, version_ack: 4 }));
+        assert!(!text.is_pending());
+        assert_eq!(text.version(), Some(4));
+
+        let done = text.done(TextResult::Accept).unwrap();
+        assert_eq!(done.session_id, 9);
+        assert_eq!(done.version, 4);
+        assert_eq!(done.flags, u32::from(TextResult::Accept.raw()));
+        assert_eq!(done.unk, 0);
+        assert_eq!(text.prompt(), None);
+    }
+
+    #[test]
+    fn long_text_is_chunked() {
+        let mut text = SystemText::new();
+        text.handle_configuration(&configuration(0));
+
+        // Multi-byte characters must not be split between chunks
+        let long = format!("abc{}", "äbc".repeat(300));
+        let chunks = text.submit(&long).unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        let mut joined = String::new();
+        for chunk in chunks.iter() {
+            assert_eq!(chunk.text_chunk_byte_start as usize, joined.len());
+            joined.push_str(chunk.text_chunk.value());
+        }
+        assert_eq!(joined, long);
+    }
+
+    #[test]
+    fn console_input_is_reassembled() {
+        let mut console = SystemText::new();
+        console.handle_configuration(&configuration(0));
+        let long = "x".repeat(MAX_CHUNK_LEN + 10);
+        let chunks = console.submit(&long).unwrap();
+
+        let mut text = SystemText::new();
+        text.handle_configuration(&configuration(0));
+
+        assert_eq!(text.handle_input(&chunks[0]), None);
+        let ack = text.handle_input(&chunks[1]).unwrap();
+        assert_eq!(ack.version_ack, 4);
+        assert_eq!(text.text(), Some(long.as_str()));
+    }
+
+    #[test]
+    fn console_chunks_are_placed_by_offset() {
+        let mut console = SystemText::new();
+        console.handle_configuration(&configuration(0));
+        let long = format!("{}{}", "x".repeat(MAX_CHUNK_LEN), "y".repeat(MAX_CHUNK_LEN + 10));
+        let chunks = console.submit(&long).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        let mut text = SystemText::new();
+        text.handle_configuration(&configuration(0));
+
+        assert_eq!(text.handle_input(&chunks[2]), None);
+        assert_eq!(text.handle_input(&chunks[0]), None);
+        assert_eq!(text.handle_input(&chunks[0]), None);
+        assert_eq!(text.handle_input(&chunks[1]).unwrap().version_ack, 4);
+        assert_eq!(text.text(), Some(long.as_str()));
+    }
+
+    #[test]
+    fn submit_requires_session() {
+        let mut text = SystemText::new();
+        assert_eq!(text.submit("hello"), None);
+        assert_eq!(text.done(TextResult::Cancel), None);
+    }
+
+    #[test]
+    fn title_text_works() {
+        let mut text = TitleText::new();
+        assert_eq!(text.input("hello", TextResult::Accept), None);
+
+        text.handle_configuration(151, &configuration(0));
+        assert_eq!(text.channel_id(), Some(151));
+
+        let selection = text.select(0, 2).unwrap();
+        assert_eq!(selection.buffer_version, 4);
+
+        let input = text.input("hello", TextResult::Accept).unwrap();
+        assert_eq!(input.session_id, 9);
+        assert_eq!(input.buffer_version, 5);
+        assert_eq!(input.result, TextResult::Accept);
+        assert_eq!(text.text(), Some("hello"));
+    }
+
+    #[test]
+    fn stale_title_updates_are_ignored() {
+        let mut text = TitleText::new();
+        text.handle_configuration(151, &configuration(0));
+        text.input("newer", TextResult::Accept);
+
+        text.handle_input(&TitleTextInputData {
+            session_id: 9,
+            buffer_version: 3,
+            result: TextResult::Accept,
+            text: SGString::from_str(String::from("older"))
+        });
+        assert_eq!(text.text(), Some("newer"));
+
+        text.handle_selection(&TitleTextSelectionData {
+            session_id: 9,
+            buffer_version: 6,
+            start: 1,
+            length: 3
+        });
+        assert_eq!(text.selection(), Some((1, 3)));
+        assert_eq!(text.buffer_version(), Some(6));
+    }
+
+    #[test]
+    fn console_delta_is_applied() {
+        let mut text = SystemText::new();
+        text.handle_configuration(&configuration(0));
+        let mut input = text.submit("hello").unwrap().remove(0);
+        text.handle_acknowledge(&SystemTextAcknowledgeData { session_id: 9, version_ack: 4 });
+
+        input.base_version = 4;
+        input.submitted_version = 5;
+        input.delta = Some(vec![TextDelta {
+            offset: 0,
+            delete_count: 1,
+            insert_content: SGString::from_str(String::from("j"))
+        }]);
+
+        assert_eq!(text.handle_input(&input).unwrap().version_ack, 5);
+        assert_eq!(text.text(), Some("jello"));
+    }
+}
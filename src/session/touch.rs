@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use protocol::DynArray;
+
+use crate::packet::message::{TouchAction, TouchData, Touchpoint};
+
+/// The time between two touch reports of a moving gesture, about 60 per second
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Where touch input goes
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TouchTarget {
+    /// The system UI, as `SystemTouch` over the `ServiceChannel::SystemInput` channel
+    System,
+    /// The title with the given id, as `TitleTouch` over its `ServiceChannel::Title` channel
+    Title(u32)
+}
+
+/// A touch gesture as the sequence of touch reports making it up
+///
+/// Every report lists all fingers on the surface, each finger keeps its id
+/// from the report putting it down to the one lifting it up. Timestamps are
+/// only filled in by `reports`, so a gesture can be built once and sent
+/// repeatedly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gesture {
+    /// The touchpoints of every report along with its offset from the start in milliseconds
+    frames: Vec<(u32, Vec<Touchpoint>)>
+}
+
+impl Gesture {
+    /// A single finger touching down at `(x, y)` and lifting right away
+    pub fn tap(x: u32, y: u32) -> Self {
+        Gesture::drag(&[((x, y), (x, y))], Duration::from_millis(0))
+    }
+
+    /// A single finger moving from `from` to `to` in `duration`
+    pub fn swipe(from: (u32, u32), to: (u32, u32), duration: Duration) -> Self {
+        Gesture::drag(&[(from, to)], duration)
+    }
+
+    /// Fingers moving at the same time, each along a straight line from its start to its end
+    ///
+    /// # Arguments
+    /// * paths - the start and end position of every finger
+    /// * duration - how long the fingers take from the start to the end
+    pub fn drag(paths: &[((u32, u32), (u32, u32))], duration: Duration) -> Self {
+        let interval = FRAME_INTERVAL.as_millis() as u32;
+        let steps = (duration.as_millis() as u32 / interval).max(1);
+
+        let frame = |step: u32, action: TouchAction| -> Vec<Touchpoint> {
+            paths.iter().enumerate()
+                .map(|(index, &(from, to))| Touchpoint {
+                    id: index as u32 + 1,
+                    action,
+                    x: interpolate(from.0, to.0, step, steps),
+                    y: interpolate(from.1, to.1, step, steps)
+                })
+                .collect()
+        };
+
+        let mut frames = vec![(0, frame(0, TouchAction::Down))];
+        for step in 1..steps {
+            frames.push((step * interval, frame(step, TouchAction::Move)));
+        }
+        frames.push((steps * interval, frame(steps, TouchAction::Up)));
+
+        Gesture {
+            frames
+        }
+    }
+
+    /// How long the gesture takes from the first to the last report
+    pub fn duration(&self) -> Duration {
+        let last = self.frames.last().map_or(0, |&(offset, _)| offset);
+        Duration::from_millis(u64::from(last))
+    }
+
+    /// The reports making up the gesture, timestamped from `start` in milliseconds
+    pub fn reports(&self, start: u32) -> Vec<TouchData> {
+        self.frames.iter()
+            .map(|&(offset, ref touchpoints)| TouchData {
+                timestamp: start.wrapping_add(offset),
+                touchpoints: DynArray::new(touchpoints.clone())
+            })
+            .collect()
+    }
+}
+
+fn interpolate(from: u32, to: u32, step: u32, steps: u32) -> u32 {
+    let from = i64::from(from);
+    let to = i64::from(to);
+    (from + (to - from) * i64::from(step) / i64::from(steps)) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tap_works() {
+        let reports = Gesture::tap(100, 200).reports(1000);
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].timestamp, 1000);
+        assert_eq!(reports[0].touchpoints.elements, vec![Touchpoint { id: 1, action: TouchAction::Down, x: 100, y: 200 }]);
+        assert_eq!(reports[1].timestamp, 1016);
+        assert_eq!(reports[1].touchpoints.elements, vec![Touchpoint { id: 1, action: TouchAction::Up, x: 100, y: 200 }]);
+    }
+
+    #[test]
+    fn swipe_works() {
+        let gesture = Gesture::swipe((0, 100), (320, 100), Duration::from_millis(64));
+        let reports = gesture.reports(0);
+
+        assert_eq!(gesture.duration(), Duration::from_millis(64));
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[0].touchpoints.elements[0].action, TouchAction::Down);
+        assert_eq!(reports[2].touchpoints.elements[0].action, TouchAction::Move);
+        assert_eq!(reports[2].touchpoints.elements[0].x, 160);
+        assert_eq!(reports[4].touchpoints.elements[0].action, TouchAction::Up);
+        assert_eq!(reports[4].touchpoints.elements[0].x, 320);
+    }
+
+    #[test]
+    fn drag_moves_every_finger() {
+        let reports = Gesture::drag(&[((0, 0), (0, 100)), ((50, 100), (50, 0))], Duration::from_millis(32)).reports(0);
+
+        for report in reports.iter() {
+            let ids: Vec<u32> = report.touchpoints.elements.iter().map(|touchpoint| touchpoint.id).collect();
+            assert_eq!(ids, vec![1, 2]);
+        }
+        assert_eq!(reports[1].touchpoints.elements[0].y, 50);
+        assert_eq!(reports[1].touchpoints.elements[1].y, 50);
+        assert_eq!(reports[2].touchpoints.elements[1].y, 0);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use crate::packet::Packet;
+use crate::packet::message::{MediaCommandResultData, Message, MessageHeader};
+#[cfg(feature = "json")]
+use crate::packet::message::JsonData;
+use crate::session::SessionError;
+use crate::session::channel::ServiceChannel;
+use crate::session::text::TextPrompt;
+
+/// How many packets an `Inbox` keeps, older ones are dropped
+pub const MAX_INBOX: usize = 256;
+
+/// Packets that arrived while a client was waiting for something else
+///
+/// Clients offer every packet they receive while waiting for a reply to
+/// the inbox, which hands back what the wait was for and keeps the rest
+/// for `recv`. Only the last `MAX_INBOX` packets are kept, so a client
+/// that is never drained doesn't grow without bound.
+pub struct Inbox {
+    packets: VecDeque<Packet>
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Inbox {
+            packets: VecDeque::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Keeps `packet` for `pop`, dropping the oldest packet if the inbox is full
+    pub fn push(&mut self, packet: Packet) {
+        if self.packets.len() == MAX_INBOX {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(packet);
+    }
+
+    /// The oldest packet that was kept
+    pub fn pop(&mut self) -> Option<Packet> {
+        self.packets.pop_front()
+    }
+
+    /// Removes the oldest kept message `matches` picks something out of and returns that
+    pub fn take_matching<T, F>(&mut self, matches: &mut F) -> Option<T>
+        where F: FnMut(&Message) -> Option<T> {
+        let (index, found) = self.packets.iter().enumerate()
+            .filter_map(|(index, packet)| match *packet {
+                Packet::Message(_, ref message) => matches(message).map(|found| (index, found)),
+                _ => None
+            })
+            .next()?;
+
+        self.packets.remove(index);
+        Some(found)
+    }
+
+    /// Returns what `matches` picks out of `packet`, keeping `packet` if that's nothing
+    pub fn offer<T, F>(&mut self, packet: Packet, matches: &mut F) -> Option<T>
+        where F: FnMut(&Message) -> Option<T> {
+        if let Packet::Message(_, ref message) = packet {
+            if let Some(found) = matches(message) {
+                return Some(found);
+            }
+        }
+
+        self.push(packet);
+        None
+    }
+
+    /// Removes the oldest kept message on the channel `channel_id` and returns it
+    pub fn take_on(&mut self, channel_id: u64) -> Option<(MessageHeader, Message)> {
+        let index = self.packets.iter().position(|packet| match *packet {
+            Packet::Message(ref header, _) => header.channel_id == channel_id,
+            _ => false
+        })?;
+
+        match self.packets.remove(index) {
+            Some(Packet::Message(header, message)) => Some((header, message)),
+            _ => None
+        }
+    }
+
+    /// Returns `packet` if it is a message on the channel `channel_id`, keeping it otherwise
+    pub fn offer_on(&mut self, packet: Packet, channel_id: u64) -> Option<(MessageHeader, Message)> {
+        match packet {
+            Packet::Message(header, message) if header.channel_id == channel_id => Some((header, message)),
+            other => {
+                self.push(other);
+                None
+            }
+        }
+    }
+}
+
+/// Matches the console's answer to the `StartChannelRequest` with `request_id`
+///
+/// Gives the id of the new channel, or the error if the console refused to open it.
+pub fn channel_started(service: ServiceChannel, request_id: u32) -> impl FnMut(&Message) -> Option<Result<u64, SessionError>> {
+    move |message| match *message {
+        Message::StartChannelResponse(ref response) if response.channel_request_id == request_id => {
+            match response.result {
+                0 => Some(Ok(response.target_channel_id)),
+                result => Some(Err(SessionError::ChannelFailed(service, result)))
+            }
+        },
+        _ => None
+    }
+}
+
+/// Matches the console's result for the media command with `request_id`
+pub fn media_result(request_id: u64) -> impl FnMut(&Message) -> Option<MediaCommandResultData> {
+    move |message| match *message {
+        Message::MediaCommandResult(ref result) if result.request_id == request_id => Some(result.clone()),
+        _ => None
+    }
+}
+
+/// Matches the console opening an on-screen keyboard
+pub fn text_prompt() -> impl FnMut(&Message) -> Option<TextPrompt> {
+    |message| match *message {
+        Message::SystemTextConfiguration(ref config) => Some(TextPrompt::from_configuration(config)),
+        _ => None
+    }
+}
+
+/// Matches the console acknowledging the text we submitted as `version`
+pub fn text_acknowledged(version: u32) -> impl FnMut(&Message) -> Option<()> {
+    move |message| match *message {
+        Message::SystemTextAcknowledge(ref ack) if ack.version_ack == version => Some(()),
+        _ => None
+    }
+}
+
+/// Matches a title asking for text
+pub fn title_text_prompt() -> impl FnMut(&Message) -> Option<TextPrompt> {
+    |message| match *message {
+        Message::TitleTextConfiguration(ref config) => Some(TextPrompt::from_configuration(config)),
+        _ => None
+    }
+}
+
+/// Matches any `Json` message
+#[cfg(feature = "json")]
+pub fn json() -> impl FnMut(&Message) -> Option<JsonData> {
+    |message| match *message {
+        Message::Json(ref data) => Some(data.clone()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::Type;
+    use crate::packet::message::{DisconnectData, MessageHeaderFlags};
+
+    fn packet(channel_id: u64, message: Message) -> Packet {
+        let header = MessageHeader {
+            pkt_type: Type::Message,
+            protected_payload_length: 0,
+            sequence_number: 1,
+            target_participant_id: 0,
+            source_participant_id: 0,
+            flags: MessageHeaderFlags {
+                msg_type: message.msg_type(),
+                need_ack: false,
+                is_fragment: false,
+                version: 2
+            },
+            channel_id
+        };
+        Packet::Message(header, message)
+    }
+
+    fn result(request_id: u64) -> Message {
+        Message::MediaCommandResult(MediaCommandResultData { request_id, result: 0 })
+    }
+
+    fn disconnect() -> Message {
+        Message::Disconnect(DisconnectData { reason: 0, error_code: 0 })
+    }
+
+    #[test]
+    fn unmatched_packets_are_kept_in_order() {
+        let mut inbox = Inbox::new();
+        let mut matches = media_result(2);
+
+        assert_eq!(inbox.offer(packet(0, result(1)), &mut matches), None);
+        assert_eq!(inbox.offer(packet(0, disconnect()), &mut matches), None);
+        assert_eq!(inbox.offer(packet(0, result(2)), &mut matches), Some(MediaCommandResultData { request_id: 2, result: 0 }));
+        assert_eq!(inbox.len(), 2);
+
+        assert_eq!(inbox.take_matching(&mut media_result(1)), Some(MediaCommandResultData { request_id: 1, result: 0 }));
+        assert_eq!(inbox.take_matching(&mut media_result(1)), None);
+        match inbox.pop() {
+            Some(Packet::Message(_, Message::Disconnect(_))) => {},
+            _ => panic!("Wrong packet")
+        }
+        assert!(inbox.is_empty());
+    }
+
+    #[test]
+    fn messages_are_taken_by_channel() {
+        let mut inbox = Inbox::new();
+
+        assert!(inbox.offer_on(packet(0, disconnect()), 24).is_none());
+        assert!(inbox.offer_on(packet(24, result(1)), 25).is_none());
+        assert_eq!(inbox.take_on(24).map(|(header, _)| header.channel_id), Some(24));
+        assert!(inbox.take_on(24).is_none());
+        assert_eq!(inbox.len(), 1);
+    }
+
+    #[test]
+    fn full_inboxes_drop_the_oldest_packet() {
+        let mut inbox = Inbox::new();
+        for request_id in 0..MAX_INBOX as u64 + 1 {
+            inbox.push(packet(0, result(request_id)));
+        }
+
+        assert_eq!(inbox.len(), MAX_INBOX);
+        assert_eq!(inbox.take_matching(&mut media_result(0)), None);
+        assert_eq!(inbox.take_matching(&mut media_result(1)), Some(MediaCommandResultData { request_id: 1, result: 0 }));
+    }
+}
@@ -1,27 +1,129 @@
 extern crate rustc_serialize;
-extern crate ring;
-extern crate untrusted;
-extern crate crypto;
+extern crate aes;
+extern crate cbc;
+extern crate hmac;
+extern crate sha2;
+extern crate p256;
+extern crate p384;
+extern crate p521;
+extern crate rand_core;
+#[cfg(feature = "openssl")]
+extern crate openssl;
+
+use std::fmt;
 
 use self::rustc_serialize::hex::FromHex;
-use self::ring::{agreement, hmac, rand, digest};
-use self::ring::error::Unspecified;
-use self::untrusted::Input;
-use self::crypto::aes;
-use self::crypto::aes::KeySize;
-use self::crypto::blockmodes;
-use self::crypto::symmetriccipher::SymmetricCipherError;
-use self::crypto::buffer::{RefReadBuffer, RefWriteBuffer, BufferResult};
+use self::aes::Aes128;
+use self::aes::cipher::{BlockDecryptMut, BlockEncryptMut, InvalidLength, KeyIvInit};
+use self::aes::cipher::block_padding::{NoPadding, Pkcs7, UnpadError};
+use self::aes::cipher::inout::PadError;
+use self::hmac::{Hmac, Mac};
+use self::hmac::digest::MacError;
+use self::sha2::{Digest, Sha256, Sha512};
+use self::p256::NistP256;
+use self::p256::elliptic_curve::{self, ecdh, AffinePoint, CurveArithmetic, FieldBytesSize, SecretKey};
+use self::p256::elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use self::p384::NistP384;
+use self::p521::NistP521;
+use self::rand_core::{OsRng, RngCore};
+#[cfg(feature = "openssl")]
+use self::openssl::ec::EcKeyRef;
+#[cfg(feature = "openssl")]
+use self::openssl::pkey::Private;
+use zeroize::Zeroize;
+
+use crate::util::{Certificate, PublicKey, PublicKeyType};
+
+type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
+type HmacSha256 = Hmac<Sha256>;
 
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
-        // Todo: Add more errors here?
-        Unspecified {
-            from(SymmetricCipherError)
-            from(Unspecified)
+         KeyLength {
+            from(InvalidLength)
+            display("The key or IV has the wrong length")
+         }
+         BufferOverflow {
+            from(PadError)
+            display("The output buffer is too small for the data")
+         }
+         Padding {
+            from(UnpadError)
+            display("The ciphertext isn't made of whole blocks or isn't padded to the plaintext")
+         }
+         Signature {
+            from(MacError)
+            display("The signature doesn't match the data")
+         }
+         PublicKey {
+            from(elliptic_curve::Error)
+            display("The public key isn't a point on the curve")
+         }
+         PublicKeyLength(len: usize) {
+            display("A public key of {} bytes doesn't fit its curve", len)
+         }
+         Random(err: rand_core::Error) { from() }
+         KeyType {
+            display("The keys aren't on the same supported curve")
+         }
+         SecretLength(len: usize) {
+            display("A shared secret is {} bytes, not {}", SECRET_LEN, len)
+         }
+         PrivateKey {
+            display("The private key isn't valid on its curve")
          }
-         BufferOverflow { }
+    }
+}
+
+/// The length of a `SharedSecret`
+pub const SECRET_LEN: usize = 64;
+
+/// The session keys of a `Crypto`, which is all it takes to read and write the session's packets
+///
+/// These are the AES key, the IV key and the HMAC key in that order, along
+/// with the curve the session was negotiated on. They are wiped from memory
+/// when the secret is dropped.
+pub struct SharedSecret {
+    key_type: PublicKeyType,
+    keys: [u8; SECRET_LEN]
+}
+
+impl SharedSecret {
+    /// Imports a secret previously exported with `key_type` and `as_bytes`
+    pub fn from_bytes(key_type: PublicKeyType, bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != SECRET_LEN {
+            return Err(Error::SecretLength(bytes.len()));
+        }
+
+        let mut secret = SharedSecret {
+            key_type,
+            keys: [0u8; SECRET_LEN]
+        };
+        secret.keys.copy_from_slice(bytes);
+        Ok(secret)
+    }
+
+    /// The curve of the session the keys belong to
+    pub fn key_type(&self) -> PublicKeyType {
+        self.key_type
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.keys
+    }
+}
+
+impl fmt::Debug for SharedSecret {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "SharedSecret(..)")
+    }
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.keys.zeroize();
     }
 }
 
@@ -44,10 +146,39 @@ impl Salt {
     }
 }
 
+/// Fills a buffer with cryptographically secure random bytes
+///
+/// # Arguments
+/// * buf - the buffer to be filled
+pub fn random_bytes(buf: &mut [u8]) -> Result<(), Error> {
+    OsRng.try_fill_bytes(buf)?;
+    Ok(())
+}
+
+/// Generates a key pair on the curve of `key_type`, as a console has in its certificate
+///
+/// Returns the private key as a big endian number for `Crypto::from_private_scalar`
+/// and the public key as an uncompressed point.
+pub fn generate_key_pair(key_type: PublicKeyType) -> (Vec<u8>, Vec<u8>) {
+    match key_type {
+        PublicKeyType::EcDhP256 => key_pair::<NistP256>(),
+        PublicKeyType::EcDhP384 => key_pair::<NistP384>(),
+        PublicKeyType::EcDhP521 => key_pair::<NistP521>()
+    }
+}
+
+fn key_pair<C>() -> (Vec<u8>, Vec<u8>)
+    where C: CurveArithmetic,
+          AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+          FieldBytesSize<C>: ModulusSize {
+    let key = SecretKey::<C>::random(&mut OsRng);
+    (key.to_bytes().to_vec(), key.public_key().to_encoded_point(false).as_bytes().to_vec())
+}
+
 /// The particular crypto ipmlementation used by SmartGlass
-#[allow(dead_code)]
 pub struct Crypto {
-    pub_key: [u8; 40],
+    key_type: PublicKeyType,
+    pub_key: Vec<u8>,
     aes_key: [u8;16],
     iv_key: [u8;16],
     hmac_key: [u8;32]
@@ -57,51 +188,155 @@ impl Crypto {
     /// Creates a new Crypto
     ///
     /// # Arguments
-    /// * foreignPublicKey - The public key of the xbox one associated with the SG Session
-    pub fn new(foreign_public_key: &[u8]) -> Crypto {
-        let rng = rand::SystemRandom::new();
-        // TODO: error handling
-        Crypto::from_rand(foreign_public_key, rng)
+    /// * key_type - the curve of the console's key
+    /// * foreign_public_key - The public key of the xbox one associated with the SG Session, as an uncompressed point
+    pub fn new(key_type: PublicKeyType, foreign_public_key: &[u8]) -> Result<Crypto, Error> {
+        match key_type {
+            PublicKeyType::EcDhP256 => Crypto::agree(key_type, &SecretKey::<NistP256>::random(&mut OsRng), foreign_public_key),
+            PublicKeyType::EcDhP384 => Crypto::agree(key_type, &SecretKey::<NistP384>::random(&mut OsRng), foreign_public_key),
+            PublicKeyType::EcDhP521 => Crypto::agree(key_type, &SecretKey::<NistP521>::random(&mut OsRng), foreign_public_key)
+        }
+    }
+
+    /// Creates a new Crypto for the key of the console's certificate
+    pub fn from_certificate(certificate: &Certificate) -> Result<Crypto, Error> {
+        Crypto::new(certificate.public_key_type(), &certificate.public_key_point())
     }
 
-    fn from_rand<T>(foreign_public_key: &[u8], rng: T) -> Crypto
-        where T: rand::SecureRandom {
-         // Safe to unwrap here because the string is static and known to be valid
+    /// Creates the console's side of a Crypto
+    ///
+    /// Derives the same keys as the client's `Crypto::new` from the other
+    /// halves of the key pairs.
+    ///
+    /// # Arguments
+    /// * private_key - the key of the console's certificate
+    /// * client_public_key - the client's public key from the connect request
+    #[cfg(feature = "openssl")]
+    pub fn from_private_key(private_key: &EcKeyRef<Private>, client_public_key: &PublicKey) -> Result<Crypto, Error> {
+        let key_type = private_key.group().curve_name()
+            .and_then(PublicKeyType::from_nid)
+            .ok_or(Error::KeyType)?;
+
+        let mut scalar = private_key.private_key().to_vec_padded(key_type.key_len() as i32 / 2)
+            .map_err(|_| Error::PrivateKey)?;
+        let crypto = Crypto::from_private_scalar(key_type, &scalar, client_public_key);
+        scalar.zeroize();
+        crypto
+    }
+
+    /// Creates the console's side of a Crypto from the raw private key
+    ///
+    /// Derives the same keys as the client's `Crypto::new` from the other
+    /// halves of the key pairs.
+    ///
+    /// # Arguments
+    /// * key_type - the curve of the console's key
+    /// * scalar - the console's private key as a big endian number, as from `generate_key_pair`
+    /// * client_public_key - the client's public key from the connect request
+    pub fn from_private_scalar(key_type: PublicKeyType, scalar: &[u8], client_public_key: &PublicKey) -> Result<Crypto, Error> {
+        if key_type != client_public_key.key_type() {
+            return Err(Error::KeyType);
+        }
+        let client_point = [&[0x04][..], client_public_key.key()].concat();
+
+        match key_type {
+            PublicKeyType::EcDhP256 => Crypto::agree(key_type, &SecretKey::<NistP256>::from_slice(scalar).map_err(|_| Error::PrivateKey)?, &client_point),
+            PublicKeyType::EcDhP384 => Crypto::agree(key_type, &SecretKey::<NistP384>::from_slice(scalar).map_err(|_| Error::PrivateKey)?, &client_point),
+            PublicKeyType::EcDhP521 => Crypto::agree(key_type, &SecretKey::<NistP521>::from_slice(scalar).map_err(|_| Error::PrivateKey)?, &client_point)
+        }
+    }
+
+    /// The ECDH key agreement between our private key and the other side's public key
+    fn agree<C>(key_type: PublicKeyType, private_key: &SecretKey<C>, foreign_public_key: &[u8]) -> Result<Crypto, Error>
+        where C: CurveArithmetic,
+              AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+              FieldBytesSize<C>: ModulusSize {
+        let foreign_key = elliptic_curve::PublicKey::<C>::from_sec1_bytes(foreign_public_key)?;
+        let shared_secret = ecdh::diffie_hellman(private_key.to_nonzero_scalar(), foreign_key.as_affine());
+        let public_key = private_key.public_key().to_encoded_point(false);
+
+        // Skip the leading point format byte, SmartGlass only sends the coordinates
+        Ok(Crypto::from_shared_secret(key_type, public_key.as_bytes()[1..].to_vec(), shared_secret.raw_secret_bytes()))
+    }
+
+    /// Derives the session keys from the result of the ECDH key agreement
+    ///
+    /// # Arguments
+    /// * key_type - the curve the keys are on
+    /// * pub_key - our public key, without the point format byte
+    /// * shared_secret - the x coordinate of the shared point
+    fn from_shared_secret(key_type: PublicKeyType, pub_key: Vec<u8>, shared_secret: &[u8]) -> Crypto {
+        // Safe to unwrap here because the string is static and known to be valid
         //  text. We should never recieve anthing but Ok(val)
         let prepend_salt = Salt::Prepend("D637F1AAE2F0418C".from_hex().unwrap());
         let append_salt = Salt::Append("A8F81A574E228AB7".from_hex().unwrap());
-        let salts = [prepend_salt, append_salt];
-
-        let untrusted_foreign_key = Input::from(foreign_public_key);
-        let private_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
-        let mut public_key = [0u8; agreement::PUBLIC_KEY_MAX_LEN];
-        let public_key = &mut public_key[..private_key.public_key_len()];
-        // TODO: error handling
-        private_key.compute_public_key(public_key).unwrap();
-
-        let kdf =  |secret: &[u8]| {
-            // TODO: error handling
-            let mut salted_secret = secret.to_vec();
-            for salt in salts.iter() {
-                salted_secret = salt.apply(&salted_secret);
-            }
-            let derived_key = digest::digest(&digest::SHA512, &salted_secret[..]);
-            Ok((*derived_key.as_ref()).to_vec())
+
+        let mut secret = shared_secret.to_vec();
+        let mut prepended = prepend_salt.apply(&secret);
+        let mut salted_secret = append_salt.apply(&prepended);
+        let mut derived_key = Sha512::digest(&salted_secret[..]);
+
+        let mut crypto = Crypto {
+            key_type,
+            pub_key,
+            aes_key: [0u8; 16],
+            iv_key: [0u8; 16],
+            hmac_key: [0u8; 32]
+        };
+        crypto.aes_key.copy_from_slice(&derived_key[0..16]);
+        crypto.iv_key.copy_from_slice(&derived_key[16..32]);
+        crypto.hmac_key.copy_from_slice(&derived_key[32..64]);
+
+        secret.zeroize();
+        prepended.zeroize();
+        salted_secret.zeroize();
+        derived_key.as_mut_slice().zeroize();
+        crypto
+    }
+
+    /// Re-creates a Crypto from the keys of an earlier session
+    ///
+    /// The public key isn't part of the secret, the restored Crypto has an
+    /// all-zero key on the secret's curve instead. It reads and writes packets
+    /// of the session the secret came from, but a console won't accept a new
+    /// connect request made with it.
+    pub fn from_secret(secret: &SharedSecret) -> Crypto {
+        let key_type = secret.key_type();
+        let mut crypto = Crypto {
+            key_type,
+            pub_key: vec![0u8; key_type.key_len()],
+            aes_key: [0u8; 16],
+            iv_key: [0u8; 16],
+            hmac_key: [0u8; 32]
         };
 
-        let derived_key = agreement::agree_ephemeral(private_key, &agreement::ECDH_P256,
-            untrusted_foreign_key, ring::error::Unspecified, kdf).unwrap();
+        let keys = secret.as_bytes();
+        crypto.aes_key.copy_from_slice(&keys[0..16]);
+        crypto.iv_key.copy_from_slice(&keys[16..32]);
+        crypto.hmac_key.copy_from_slice(&keys[32..64]);
+        crypto
+    }
+
+    /// Exports the session keys, `from_secret` turns them back into a Crypto
+    pub fn secret(&self) -> SharedSecret {
+        let mut secret = SharedSecret {
+            key_type: self.key_type,
+            keys: [0u8; SECRET_LEN]
+        };
+        secret.keys[0..16].copy_from_slice(&self.aes_key);
+        secret.keys[16..32].copy_from_slice(&self.iv_key);
+        secret.keys[32..64].copy_from_slice(&self.hmac_key);
+        secret
+    }
 
-        let mut pub_key = [0u8; 40];
-        let mut aes_key = [0u8; 16];
-        let mut iv_key = [0u8; 16];
-        let mut hmac_key = [0u8; 32];
-        &pub_key.clone_from_slice(&public_key[0..40]);
-        &aes_key.clone_from_slice(&derived_key[0..16]);
-        &iv_key.clone_from_slice(&derived_key[16..32]);
-        &hmac_key.clone_from_slice(&derived_key[32..64]);
+    /// The curve of our key
+    pub fn key_type(&self) -> PublicKeyType {
+        self.key_type
+    }
 
-        Crypto{pub_key, aes_key, iv_key, hmac_key}
+    /// Returns the public half of our key, without the point format byte
+    pub fn public_key(&self) -> &[u8] {
+        &self.pub_key
     }
 
     /// Calculates the number of bytes needed to hold the input after padding is applied
@@ -120,35 +355,38 @@ impl Crypto {
     /// * plaintext - the plaintext to be encrypted
     /// * ciphertext - the result of the encryption (use Crypto::aligned_len(plaintext) to determine the size this slice must be)
     pub fn encrypt(&self, iv: &[u8], plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
-        let mut read_buf = RefReadBuffer::new(plaintext);
-        let mut write_buf = RefWriteBuffer::new(ciphertext);
-        let mut encryptor = 
-            match plaintext.len() % 16 {
-                0 => aes::cbc_encryptor(KeySize::KeySize128, &self.aes_key, iv, blockmodes::NoPadding),
-                _ => aes::cbc_encryptor(KeySize::KeySize128, &self.aes_key, iv, blockmodes::PkcsPadding)
-            };
-        let res = encryptor.encrypt(&mut read_buf, &mut write_buf, true)?;
-        match res {
-            BufferResult::BufferOverflow => Err(Error::BufferOverflow),
-            _ => Ok(())
-        }
+        let encryptor = Aes128CbcEnc::new_from_slices(&self.aes_key, iv)?;
+        // Whole blocks go out as they are, anything else is padded
+        match plaintext.len() % 16 {
+            0 => encryptor.encrypt_padded_b2b_mut::<NoPadding>(plaintext, ciphertext)?,
+            _ => encryptor.encrypt_padded_b2b_mut::<Pkcs7>(plaintext, ciphertext)?
+        };
+        Ok(())
     }
 
     /// Decrypts a ciphertext into a plaintext
     ///
+    /// A ciphertext longer than `plaintext` must end in the PKCS#7 padding for that length.
+    ///
     /// # Arguments
     /// * iv - the IV used during encryption (must be exactly 16 bytes)
     /// * ciphertext - the ciphertext to be decrypted
-    /// * plaintext - the result of the decryption (length is awkward here, will need to fix)
+    /// * plaintext - the result of the decryption, as long as the data before it was padded
     pub fn decrypt(&self, iv: &[u8], ciphertext: &[u8], plaintext: &mut [u8]) -> Result<(), Error> {
-        let mut read_buf = RefReadBuffer::new(ciphertext);
-        let mut write_buf = RefWriteBuffer::new(plaintext);
-        let mut decryptor = aes::cbc_decryptor(KeySize::KeySize128, &self.aes_key, iv, blockmodes::PkcsPadding);
-        let res = decryptor.decrypt(&mut read_buf, &mut write_buf, true)?;
-        match res {
-            BufferResult::BufferOverflow => Err(Error::BufferOverflow),
-            _ => Ok(())
+        if plaintext.len() > ciphertext.len() {
+            return Err(Error::BufferOverflow);
+        }
+
+        let mut decrypted = vec![0u8; ciphertext.len()];
+        Aes128CbcDec::new_from_slices(&self.aes_key, iv)?
+            .decrypt_padded_b2b_mut::<NoPadding>(ciphertext, &mut decrypted)?;
+        // Whole blocks come in as they are, anything shorter was padded
+        let padded = pkcs7_padded(&decrypted, plaintext.len());
+        if padded {
+            plaintext.copy_from_slice(&decrypted[..plaintext.len()]);
         }
+        decrypted.zeroize();
+        if padded { Ok(()) } else { Err(Error::Padding) }
     }
 
     /// Encryptes the plaintext using the IV key
@@ -157,14 +395,9 @@ impl Crypto {
     /// * plaintext - the plaintext to be encrypted
     /// * ciphertext - the result of the encryption
     pub fn generate_iv(&self, plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
-        let mut read_buf = RefReadBuffer::new(plaintext);
-        let mut write_buf = RefWriteBuffer::new(ciphertext);
-        let mut encryptor = aes::cbc_encryptor(KeySize::KeySize128, &self.iv_key, &[0u8;16], blockmodes::NoPadding);
-        let res = encryptor.encrypt(&mut read_buf, &mut write_buf, true)?;
-        match res {
-            BufferResult::BufferOverflow => Err(Error::BufferOverflow),
-            _ => Ok(())
-        }
+        Aes128CbcEnc::new_from_slices(&self.iv_key, &[0u8; 16])?
+            .encrypt_padded_b2b_mut::<NoPadding>(plaintext, ciphertext)?;
+        Ok(())
     }
 
     /// Creates a signature for a slice
@@ -173,9 +406,9 @@ impl Crypto {
     /// * data - the data to be signed
     /// * signature - the rusult of the signature (must be exactly 32 bytes)
     pub fn sign(&self, data: &[u8], signature: &mut [u8]) {
-        let key = hmac::SigningKey::new(&digest::SHA256, &self.hmac_key[..]);
-        let hash = hmac::sign(&key, data);
-        signature.clone_from_slice(hash.as_ref());
+        let mut mac = self.mac();
+        mac.update(data);
+        signature.clone_from_slice(&mac.finalize().into_bytes());
     }
 
     /// Verifies a signature was produced from the slice
@@ -183,28 +416,29 @@ impl Crypto {
     /// * data - the data that was signed
     /// * signature - the signature of the data
     pub fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), Error> {
-        let key = hmac::VerificationKey::new(&digest::SHA256, &self.hmac_key[..]);
-        hmac::verify(&key, data, signature)?;
+        let mut mac = self.mac();
+        mac.update(data);
+        mac.verify_slice(signature)?;
         Ok(())
     }
-}
-
-pub mod tests {
-    use super::*;
 
-    pub fn from_secret(secret: &[u8]) -> Crypto {
-        if secret.len() != 64 {
-            panic!("Secret length should be exactly 64-bytes")
-        }
+    fn mac(&self) -> HmacSha256 {
+        // HMAC takes keys of any length
+        <HmacSha256 as Mac>::new_from_slice(&self.hmac_key).unwrap()
+    }
+}
 
-        let mut aes_key = [0u8; 16];
-        let mut iv_key = [0u8; 16];
-        let mut hmac_key = [0u8; 32];
-        &aes_key.clone_from_slice(&secret[0..16]);
-        &iv_key.clone_from_slice(&secret[16..32]);
-        &hmac_key.clone_from_slice(&secret[32..64]);
+/// Whether `data` is `len` bytes followed by their PKCS#7 padding, or just `len` bytes
+fn pkcs7_padded(data: &[u8], len: usize) -> bool {
+    let padding = data.len() - len;
+    padding <= 16 && data[len..].iter().all(|&byte| usize::from(byte) == padding)
+}
 
-        Crypto{pub_key: [0u8; 40], aes_key, iv_key, hmac_key}
+impl Drop for Crypto {
+    fn drop(&mut self) {
+        self.aes_key.zeroize();
+        self.iv_key.zeroize();
+        self.hmac_key.zeroize();
     }
 }
 
@@ -215,22 +449,124 @@ mod test {
     fn new_crypto() -> Crypto {
         let foreign_public_key = "041db1e7943878b28c773228ebdcfb05b985be4a386a55f50066231360785f61b60038caf182d712d86c8a28a0e7e2733a0391b1169ef2905e4e21555b432b262d"
             .from_hex().unwrap();
-        Crypto::new(&foreign_public_key[..])
+        Crypto::new(PublicKeyType::EcDhP256, &foreign_public_key[..]).unwrap()
     }
 
     pub fn from_secret(secret: &[u8]) -> Crypto {
-        if secret.len() != 64 {
-            panic!("Secret length should be exactly 64-bytes")
+        Crypto::from_secret(&SharedSecret::from_bytes(PublicKeyType::EcDhP256, secret).unwrap())
+    }
+
+    /// The client's and the console's side of the same session, along with the console's public key
+    fn both_sides(key_type: PublicKeyType) -> (Crypto, Crypto, Vec<u8>) {
+        let (console_scalar, console_point) = generate_key_pair(key_type);
+
+        let client = Crypto::new(key_type, &console_point).unwrap();
+        let client_key = PublicKey::new(key_type, client.public_key().to_vec()).unwrap();
+        let console = Crypto::from_private_scalar(key_type, &console_scalar, &client_key).unwrap();
+        (client, console, console_point)
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_keys() {
+        for &key_type in [PublicKeyType::EcDhP256, PublicKeyType::EcDhP384, PublicKeyType::EcDhP521].iter() {
+            let (client, console, console_point) = both_sides(key_type);
+
+            assert_eq!(client.aes_key, console.aes_key);
+            assert_eq!(client.iv_key, console.iv_key);
+            assert_eq!(client.hmac_key, console.hmac_key);
+            assert_eq!(client.public_key().len(), key_type.key_len());
+            assert_eq!(console.public_key(), &console_point[1..]);
         }
+    }
+
+    #[test]
+    fn secrets_round_trip() {
+        let (client, console, _) = both_sides(PublicKeyType::EcDhP384);
+        let exported = client.secret();
+        assert_eq!(exported.key_type(), PublicKeyType::EcDhP384);
+        let secret = SharedSecret::from_bytes(exported.key_type(), exported.as_bytes()).unwrap();
+        let restored = Crypto::from_secret(&secret);
+        assert_eq!(restored.key_type(), PublicKeyType::EcDhP384);
+        assert_eq!(restored.public_key().len(), PublicKeyType::EcDhP384.key_len());
+
+        let plaintext = String::from("Test").into_bytes();
+        let iv = [0xb0u8; 16];
+        let mut ciphertext = [0u8; 16];
+        restored.encrypt(&iv, &plaintext, &mut ciphertext).unwrap();
 
-        let mut aes_key = [0u8; 16];
-        let mut iv_key = [0u8; 16];
-        let mut hmac_key = [0u8; 32];
-        &aes_key.clone_from_slice(&secret[0..16]);
-        &iv_key.clone_from_slice(&secret[16..32]);
-        &hmac_key.clone_from_slice(&secret[32..64]);
+        let mut decrypted = [0u8; 16];
+        console.decrypt(&iv, &ciphertext, &mut decrypted).unwrap();
+        assert_eq!(&decrypted[..plaintext.len()], &plaintext[..]);
+    }
+
+    #[test]
+    fn secrets_need_the_right_length() {
+        match SharedSecret::from_bytes(PublicKeyType::EcDhP256, &[0u8; 32]) {
+            Err(Error::SecretLength(32)) => {},
+            _ => panic!("Expected a secret length error")
+        }
+    }
 
-        Crypto{pub_key: [0u8; 40], aes_key, iv_key, hmac_key}
+    #[test]
+    fn public_keys_need_the_curve_length() {
+        match PublicKey::new(PublicKeyType::EcDhP384, vec![0u8; 64]) {
+            Err(Error::PublicKeyLength(64)) => {},
+            _ => panic!("Expected a public key length error")
+        }
+    }
+
+    #[test]
+    fn invalid_points_are_rejected() {
+        match Crypto::new(PublicKeyType::EcDhP256, &[0x04; 65]) {
+            Err(Error::PublicKey) => {},
+            _ => panic!("Expected a public key error")
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "openssl")]
+    fn openssl_keys_work() {
+        use openssl::bn::BigNumContext;
+        use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+
+        let group = EcGroup::from_curve_name(PublicKeyType::EcDhP521.nid()).unwrap();
+        let console_key = EcKey::generate(&group).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let console_point = console_key.public_key().to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx).unwrap();
+
+        let client = Crypto::new(PublicKeyType::EcDhP521, &console_point).unwrap();
+        let client_key = PublicKey::new(PublicKeyType::EcDhP521, client.public_key().to_vec()).unwrap();
+        let console = Crypto::from_private_key(&console_key, &client_key).unwrap();
+        assert_eq!(client.aes_key, console.aes_key);
+        assert_eq!(console.public_key(), &console_point[1..]);
+    }
+
+    #[test]
+    fn mismatched_curves_are_rejected() {
+        let (console_scalar, _) = generate_key_pair(PublicKeyType::EcDhP384);
+        let client_key = PublicKey::new(PublicKeyType::EcDhP256, vec![0u8; 64]).unwrap();
+
+        match Crypto::from_private_scalar(PublicKeyType::EcDhP384, &console_scalar, &client_key) {
+            Err(Error::KeyType) => {},
+            _ => panic!("Expected a key type error")
+        }
+    }
+
+    #[test]
+    fn console_can_read_client_messages() {
+        let (client, console, _) = both_sides(PublicKeyType::EcDhP256);
+
+        let plaintext = String::from("Test").into_bytes();
+        let iv = [0xb0u8; 16];
+        let mut ciphertext = vec![0u8; Crypto::aligned_len(plaintext.len())];
+        let mut signature = [0u8; 32];
+        client.encrypt(&iv, &plaintext, &mut ciphertext).unwrap();
+        client.sign(&ciphertext, &mut signature);
+
+        let mut decrypted = vec![0u8; plaintext.len()];
+        console.verify(&ciphertext, &signature).unwrap();
+        console.decrypt(&iv, &ciphertext, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
     }
 
     #[test]
@@ -290,6 +626,19 @@ mod test {
         assert_eq!(ciphertext, [0x64, 0x97, 0x23, 0x2a, 0x0e, 0x4e, 0x74, 0x34, 0x3c, 0x3a, 0x08, 0xb3, 0x68, 0x4b, 0x45, 0xf7])
     }
 
+    #[test]
+    fn whole_blocks_are_not_padded() {
+        let plaintext = [0x5au8; 32];
+        let mut ciphertext = [0u8; 32];
+        let crypto = from_secret(include_bytes!("test/secret"));
+        let iv = [0xb0u8; 16];
+        crypto.encrypt(&iv, &plaintext, &mut ciphertext).unwrap();
+
+        let mut decrypted = [0u8; 32];
+        crypto.decrypt(&iv, &ciphertext, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
     #[test]
     fn decrypt_works() {
         let plaintext = String::from("Test").into_bytes();
@@ -305,6 +654,22 @@ mod test {
         assert_eq!(plaintext, new_plaintext);
     }
 
+    #[test]
+    fn decrypt_checks_padding() {
+        let plaintext = String::from("Test").into_bytes();
+        let mut ciphertext = vec![0u8; Crypto::aligned_len(plaintext.len())];
+        let crypto = new_crypto();
+        let iv = [0xb0u8; 16];
+        crypto.encrypt(&iv[..], &plaintext[..], &mut ciphertext[..]).unwrap();
+
+        let mut too_long = vec![0u8; plaintext.len() + 1];
+        match crypto.decrypt(&iv[..], &ciphertext[..], &mut too_long[..]) {
+            Err(Error::Padding) => {},
+            _ => panic!("Bad padding was accepted")
+        }
+        assert_eq!(too_long, vec![0u8; plaintext.len() + 1]);
+    }
+
     #[test]
     fn generate_iv_works() {
         let crypto = from_secret(include_bytes!("test/secret"));
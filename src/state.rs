@@ -1,4 +1,6 @@
-use ::sgcrypto::Crypto;
+use crate::sgcrypto::Crypto;
+
+pub use crate::packet::message::PairingState;
 
 quick_error!{
     #[derive(Debug)]
@@ -12,6 +14,7 @@ quick_error!{
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum ConnectionState {
     Disconnected = 0x0,
     Connecting = 0x1,
@@ -21,14 +24,10 @@ pub enum ConnectionState {
     Reconnecting = 0x5
 }
 
-pub enum PairingState {
-    NotPaired = 0x0,
-    Paired = 0x1
-}
-
 pub struct State {
     pub connection_state: ConnectionState,
     pub pairing_state: PairingState,
+    pub participant_id: u32,
     pub crypto: Crypto,
 }
 
@@ -45,6 +44,13 @@ impl SGState {
         }
     }
 
+    pub fn ensure_connected_mut(&mut self) -> Result<&mut State, InvalidState> {
+        match *self {
+            SGState::Disconnected => Err(InvalidState::Disconnected),
+            SGState::Connected(ref mut state) => Ok(state)
+        }
+    }
+
     pub fn ensure_disconnected(&self) -> Result<(), InvalidState> {
         match *self {
             SGState::Disconnected => Ok(()),
@@ -52,5 +58,3 @@ impl SGState {
         }
     }
 }
-
-
@@ -1,13 +1,22 @@
-extern crate openssl;
-
 use std::fmt;
 use std::io::{Read, Write};
 use std::marker::PhantomData;
-
+use std::time::Duration;
+
+use const_oid::ObjectIdentifier;
+use const_oid::db::rfc4519::CN;
+use const_oid::db::rfc5912::{SECP_256_R_1, SECP_384_R_1, SECP_521_R_1};
+use num_traits::FromPrimitive;
+#[cfg(feature = "openssl")]
+use openssl::nid::Nid;
 use uuid::Uuid;
-use protocol::{Parcel, DynArray, Error};
+use x509_cert::Certificate as X509;
+use x509_cert::der::Decode;
+use protocol::{Parcel, DynArray, Error, ErrorKind};
 use protocol::String as PrefixedString;
 
+use crate::sgcrypto;
+
 /// A representation of the weird serialization format of strings in SG packets
 /// NOTE: this will not work with serde
 #[derive(Debug,PartialEq,Clone)]
@@ -111,42 +120,131 @@ impl Parcel for UUID<u8> {
     }
 }
 
-#[derive(Clone)]
+/// The curve of an elliptic curve key used for the key agreement
+#[repr(u16)]
+#[derive(Primitive, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PublicKeyType {
+    EcDhP256 = 0x0,
+    EcDhP384 = 0x1,
+    EcDhP521 = 0x2
+}
+
+impl PublicKeyType {
+    /// The object identifier of the curve in certificates
+    pub fn oid(&self) -> ObjectIdentifier {
+        match *self {
+            PublicKeyType::EcDhP256 => SECP_256_R_1,
+            PublicKeyType::EcDhP384 => SECP_384_R_1,
+            PublicKeyType::EcDhP521 => SECP_521_R_1
+        }
+    }
+
+    pub fn from_oid(oid: ObjectIdentifier) -> Option<Self> {
+        match oid {
+            SECP_256_R_1 => Some(PublicKeyType::EcDhP256),
+            SECP_384_R_1 => Some(PublicKeyType::EcDhP384),
+            SECP_521_R_1 => Some(PublicKeyType::EcDhP521),
+            _ => None
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    pub fn nid(&self) -> Nid {
+        match *self {
+            PublicKeyType::EcDhP256 => Nid::X9_62_PRIME256V1,
+            PublicKeyType::EcDhP384 => Nid::SECP384R1,
+            PublicKeyType::EcDhP521 => Nid::SECP521R1
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    pub fn from_nid(nid: Nid) -> Option<Self> {
+        match nid {
+            Nid::X9_62_PRIME256V1 => Some(PublicKeyType::EcDhP256),
+            Nid::SECP384R1 => Some(PublicKeyType::EcDhP384),
+            Nid::SECP521R1 => Some(PublicKeyType::EcDhP521),
+            _ => None
+        }
+    }
+
+    /// The length of both coordinates of a public key on the curve
+    pub fn key_len(&self) -> usize {
+        match *self {
+            PublicKeyType::EcDhP256 => 64,
+            PublicKeyType::EcDhP384 => 96,
+            PublicKeyType::EcDhP521 => 132
+        }
+    }
+}
+
+impl Parcel for PublicKeyType {
+    fn read(read: &mut Read) -> Result<Self, Error> {
+        let value = u16::read(read)?;
+        PublicKeyType::from_u16(value)
+            .ok_or_else(|| Error::from_kind(ErrorKind::Msg(format!("Unknown public key type {:#x}", value))))
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), Error> {
+        (*self as u16).write(write)
+    }
+}
+
+/// An elliptic curve public key as sent in the connect request
+///
+/// Only the coordinates of the point are sent, their length depends on the curve.
+#[derive(Clone, Debug, PartialEq)]
 pub struct PublicKey {
-    key_type: u16,
-    key: [u8; 64]
+    key_type: PublicKeyType,
+    key: Vec<u8>
 }
 
 impl PublicKey {
-    pub fn new(key_type: u16, key: [u8; 64]) -> Self {
-        PublicKey {
+    /// Creates a `PublicKey`, `key` needs to be `key_type.key_len()` bytes long
+    pub fn new(key_type: PublicKeyType, key: Vec<u8>) -> Result<Self, sgcrypto::Error> {
+        if key.len() != key_type.key_len() {
+            return Err(sgcrypto::Error::PublicKeyLength(key.len()));
+        }
+
+        Ok(PublicKey {
             key_type,
             key
-        }
+        })
     }
-}
 
-implement_composite_type!(PublicKey { key_type, key });
+    pub fn key_type(&self) -> PublicKeyType {
+        self.key_type
+    }
 
-impl fmt::Debug for PublicKey {
-    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "PublicKey {{ key_type: {}", self.key_type)?;
-        self.key[..].fmt(formatter)?;
-        write!(formatter, "}}")
+    /// The coordinates of the point, without the point format byte
+    pub fn key(&self) -> &[u8] {
+        &self.key
     }
 }
 
-impl PartialEq for PublicKey {
-    fn eq(&self, other: &PublicKey) -> bool {
-        self.key_type == other.key_type && self.key[..] == other.key[..]
+impl Parcel for PublicKey {
+    fn read(read: &mut Read) -> Result<Self, Error> {
+        let key_type = PublicKeyType::read(read)?;
+        let mut key = vec![0u8; key_type.key_len()];
+        read.read_exact(&mut key)?;
+
+        Ok(PublicKey {
+            key_type,
+            key
+        })
+    }
+
+    fn write(&self, write: &mut Write) -> Result<(), Error> {
+        self.key_type.write(write)?;
+        write.write_all(&self.key)?;
+        Ok(())
     }
 }
 
 #[derive(Clone)]
 pub struct Certificate {
     subject: String,
-    public_key_type: u8,
-    public_key: [u8; 64],
+    public_key_type: PublicKeyType,
+    public_key: Vec<u8>,
     data: DynArray<u16, u8>
 }
 
@@ -155,34 +253,47 @@ impl Certificate {
         &self.subject
     }
 
-    pub fn public_key_type(&self) -> u8 {
+    /// The curve of the console's key, which the client's key needs to be on as well
+    pub fn public_key_type(&self) -> PublicKeyType {
         self.public_key_type
     }
 
-    pub fn public_key(&self) -> &[u8; 64] {
+    /// The coordinates of the console's public key, without the point format byte
+    pub fn public_key(&self) -> &[u8] {
         &self.public_key
     }
+
+    /// Returns the public key as an uncompressed EC point, the form `Crypto::new` expects
+    pub fn public_key_point(&self) -> Vec<u8> {
+        [&[0x04][..], &self.public_key[..]].concat()
+    }
 }
 
 impl Parcel for Certificate {
     fn read(read: &mut Read) -> Result<Self, Error> {
         let data = DynArray::<u16, u8>::read(read)?;
-
-        let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::X9_62_PRIME256V1).unwrap();
-        let form = openssl::ec::POINT_CONVERSION_UNCOMPRESSED;
-        let mut ctx = openssl::bn::BigNumContext::new().unwrap();
-
-        let cert = openssl::x509::X509::from_der(data.elements.as_slice()).unwrap();
-        let subject = String::from_utf8(cert.subject_name().entries_by_nid(openssl::nid::COMMONNAME).next().unwrap().data().as_slice().to_vec()).unwrap();
-        let key = cert.public_key().unwrap().ec_key().unwrap().public_key().unwrap().to_bytes(&group, form, &mut ctx).unwrap().to_vec();
-
-        let public_key_type = key[0];
-        let mut public_key = [0u8; 64];
-
-        for (i, e) in key[1..].iter().enumerate() {
-            public_key[i] = *e;
+        let invalid = || Error::from_kind(ErrorKind::UnknownPacketId);
+
+        let cert = X509::from_der(data.elements.as_slice()).map_err(|_| invalid())?;
+        let subject = cert.tbs_certificate.subject.0.iter()
+            .flat_map(|name| name.0.iter())
+            .find(|attribute| attribute.oid == CN)
+            .ok_or_else(invalid)?;
+        let subject = String::from_utf8(subject.value.value().to_vec())?;
+
+        let key_info = &cert.tbs_certificate.subject_public_key_info;
+        let public_key_type = key_info.algorithm.parameters.as_ref()
+            .and_then(|parameters| parameters.decode_as::<ObjectIdentifier>().ok())
+            .and_then(PublicKeyType::from_oid)
+            .ok_or_else(invalid)?;
+        let key = key_info.subject_public_key.as_bytes().ok_or_else(invalid)?;
+        if key.len() != public_key_type.key_len() + 1 || key[0] != 0x04 {
+            return Err(invalid());
         }
 
+        // Skip the leading point format byte
+        let public_key = key[1..].to_vec();
+
         Ok(Certificate {
             subject,
             public_key_type,
@@ -199,7 +310,7 @@ impl Parcel for Certificate {
 
 impl fmt::Debug for Certificate {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "Certificate {{ subject: {} public_key_type: {} public_key: ", self.subject, self.public_key_type)?;
+        write!(formatter, "Certificate {{ subject: {} public_key_type: {:?} public_key: ", self.subject, self.public_key_type)?;
         self.public_key[..].fmt(formatter)?;
         write!(formatter, "}}")
     }
@@ -214,6 +325,16 @@ impl PartialEq for Certificate {
     }
 }
 
+/// Converts a timestamp in the 100ns ticks used by the console into a `Duration`
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100)
+}
+
+/// Converts a `Duration` into the 100ns ticks used by the console
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    duration.as_secs() * 10_000_000 + u64::from(duration.subsec_nanos() / 100)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -260,4 +381,19 @@ mod test {
 
         assert_eq!(sg_uuid.uuid, data);
     }
+
+    #[test]
+    fn unknown_key_types_are_named() {
+        let err = PublicKeyType::from_raw_bytes(b"\x00\x07").unwrap_err();
+        match *err.kind() {
+            ErrorKind::Msg(ref message) => assert_eq!(message, "Unknown public key type 0x7"),
+            _ => panic!("Expected an error naming the key type")
+        }
+    }
+
+    #[test]
+    fn ticks_work() {
+        assert_eq!(ticks_to_duration(15_000_001), Duration::new(1, 500_000_100));
+        assert_eq!(duration_to_ticks(Duration::new(1, 500_000_100)), 15_000_001);
+    }
 }
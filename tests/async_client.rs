@@ -0,0 +1,59 @@
+#![cfg(feature = "async")]
+
+extern crate futures;
+extern crate tokio;
+extern crate uuid;
+extern crate xbox_sg;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::net::UdpSocket;
+use xbox_sg::async_client::AsyncClient;
+use xbox_sg::packet::message::Message;
+use xbox_sg::sgcrypto;
+use xbox_sg::state::*;
+use xbox_sg::util::PublicKeyType;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn connect_and_stream_messages_works() {
+    let console = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let console_addr = console.local_addr().unwrap();
+
+    let fake_console = tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+
+        let (_, client) = console.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..2], &[0xcc, 0x00]);
+        console.send_to(include_bytes!("data/connect_response"), client).await.unwrap();
+        console.send_to(include_bytes!("data/message/console_status"), client).await.unwrap();
+    });
+
+    let mut client = AsyncClient::new(console_addr).await.unwrap();
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    client.connect_with(crypto, Uuid::nil(), String::new(), String::new(), Duration::from_secs(5)).await.unwrap();
+    assert_eq!(client.state().ensure_connected().unwrap().participant_id, 31);
+
+    let messages = client.messages();
+    futures::pin_mut!(messages);
+    let (header, message) = messages.next().await.unwrap().unwrap();
+    assert_eq!(header.sequence_number, 5);
+    match message {
+        Message::ConsoleStatus(data) => assert_eq!(data.build_number, 14393),
+        _ => panic!("Wrong type")
+    }
+
+    fake_console.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_timeout_resets_state() {
+    let console = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = AsyncClient::new(console.local_addr().unwrap()).await.unwrap();
+
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    let result = client.connect_with(crypto, Uuid::nil(), String::new(), String::new(), Duration::from_millis(100)).await;
+    assert!(result.is_err());
+    assert!(client.state().ensure_disconnected().is_ok());
+}
@@ -0,0 +1,64 @@
+extern crate uuid;
+extern crate xbox_sg;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use xbox_sg::client::{Client, ClientError};
+use xbox_sg::sgcrypto;
+use xbox_sg::state::*;
+use xbox_sg::util::*;
+use uuid::Uuid;
+
+/// Spawns a fake console on loopback that answers one discovery and one connect request
+fn spawn_console() -> (SocketAddr, thread::JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..2], &[0xdd, 0x00]);
+        socket.send_to(include_bytes!("data/discovery_response"), client).unwrap();
+
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..2], &[0xcc, 0x00]);
+        socket.send_to(include_bytes!("data/connect_response"), client).unwrap();
+    });
+
+    (addr, handle)
+}
+
+#[test]
+fn discover_and_connect_works() {
+    let (console, handle) = spawn_console();
+    let mut client = Client::new(console).unwrap();
+
+    let response = client.discover(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.name, SGString::from_str(String::from("XboxOne")));
+
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    client.connect_with(crypto, Uuid::nil(), String::new(), String::new(), Duration::from_secs(5)).unwrap();
+    handle.join().unwrap();
+
+    let state = client.state().ensure_connected().unwrap();
+    assert_eq!(state.connection_state, ConnectionState::Connected);
+    assert_eq!(state.pairing_state, PairingState::NotPaired);
+    assert_eq!(state.participant_id, 31);
+}
+
+#[test]
+fn connect_timeout_resets_state() {
+    // Bound but never answering
+    let console = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut client = Client::new(console.local_addr().unwrap()).unwrap();
+
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    match client.connect_with(crypto, Uuid::nil(), String::new(), String::new(), Duration::from_millis(100)) {
+        Err(ClientError::Timeout) => {},
+        _ => panic!("Expected a timeout")
+    }
+    assert!(client.state().ensure_disconnected().is_ok());
+}
@@ -0,0 +1,136 @@
+extern crate uuid;
+extern crate xbox_sg;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xbox_sg::discovery::Discovery;
+use xbox_sg::util::UUID;
+use uuid::Uuid;
+
+/// Spawns a fake console answering every discovery request until it's idle for a while
+///
+/// The handle yields the number of requests that were answered.
+fn spawn_responder() -> (SocketAddr, thread::JoinHandle<usize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        let mut requests = 0;
+
+        while let Ok((_, client)) = socket.recv_from(&mut buf) {
+            assert_eq!(&buf[..2], &[0xdd, 0x00]);
+            socket.send_to(include_bytes!("data/discovery_response"), client).unwrap();
+            requests += 1;
+        }
+
+        requests
+    });
+
+    (addr, handle)
+}
+
+#[test]
+fn scan_deduplicates_consoles() {
+    let (first, first_handle) = spawn_responder();
+    let (second, second_handle) = spawn_responder();
+
+    let consoles = Discovery::new()
+        .without_broadcast()
+        .unicast(first)
+        .unicast(second)
+        .timeout(Duration::from_millis(300))
+        .interval(Duration::from_millis(50))
+        .scan()
+        .unwrap();
+
+    // Both responders use the same capture, so they look like the same console
+    assert_eq!(consoles.len(), 1);
+    assert_eq!(consoles[0].name, "XboxOne");
+    assert_eq!(consoles[0].uuid, UUID::new(Uuid::parse_str("DE305D54-75B4-431B-ADB2-EB6B9E546014").unwrap()));
+    assert_eq!(consoles[0].certificate.subject(), "FFFFFFFFFFF");
+    assert!(consoles[0].address == first || consoles[0].address == second);
+
+    // Requests are repeated for the whole timeout
+    assert!(first_handle.join().unwrap() > 1);
+    assert!(second_handle.join().unwrap() > 1);
+}
+
+#[test]
+fn scan_waits_for_timeout() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let start = Instant::now();
+    let consoles = Discovery::new()
+        .without_broadcast()
+        .unicast(silent.local_addr().unwrap())
+        .timeout(Duration::from_millis(200))
+        .scan()
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(consoles.is_empty());
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+}
+
+/// Spawns a fake console that only answers discovery once it was powered on
+fn spawn_sleeping_console() -> (SocketAddr, thread::JoinHandle<usize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        let mut power_on_requests = 0;
+
+        while let Ok((_, client)) = socket.recv_from(&mut buf) {
+            match (buf[0], buf[1]) {
+                (0xdd, 0x02) => power_on_requests += 1,
+                (0xdd, 0x00) if power_on_requests > 0 => {
+                    socket.send_to(include_bytes!("data/discovery_response"), client).unwrap();
+                },
+                _ => {}
+            }
+        }
+
+        power_on_requests
+    });
+
+    (addr, handle)
+}
+
+#[test]
+fn power_on_works() {
+    let (console, handle) = spawn_sleeping_console();
+
+    let answered = Discovery::new()
+        .without_broadcast()
+        .unicast(console)
+        .timeout(Duration::from_secs(2))
+        .interval(Duration::from_millis(50))
+        .power_on("FFFFFFFFFFF")
+        .unwrap();
+
+    assert!(answered);
+    assert_eq!(handle.join().unwrap(), 5);
+}
+
+#[test]
+fn power_on_ignores_other_consoles() {
+    let (console, handle) = spawn_sleeping_console();
+
+    let answered = Discovery::new()
+        .without_broadcast()
+        .unicast(console)
+        .timeout(Duration::from_millis(300))
+        .interval(Duration::from_millis(50))
+        .power_on("FD00112233FFEE66")
+        .unwrap();
+
+    assert!(!answered);
+    handle.join().unwrap();
+}
@@ -1,17 +1,21 @@
 extern crate xbox_sg;
 extern crate protocol;
 
+use std::time::Duration;
+
 use xbox_sg::packet;
 use xbox_sg::packet::message::{Message, MessageHeader, MessageHeaderFlags, MessageType};
+use xbox_sg::packet::message::{EnabledCommands, MediaControlCommand, MediaMetadata, MediaType, PlaybackStatus, SoundLevel};
+use xbox_sg::packet::message::{SystemTextInputData, TextDelta, TextResult, TitleDisposition, TitleLaunchData, TitleLocation, TouchAction};
 use xbox_sg::state::*;
 use xbox_sg::sgcrypto;
 use xbox_sg::util::*;
 use xbox_sg::constants;
-use protocol::DynArray;
+use protocol::{DynArray, Parcel};
 
 fn new_connected_state() -> SGState {
-    let crypto = sgcrypto::tests::from_secret(include_bytes!("data/secret"));
-    let state = State{ connection_state: ConnectionState::Connecting, pairing_state: PairingState::NotPaired, crypto };
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    let state = State{ connection_state: ConnectionState::Connecting, pairing_state: PairingState::NotPaired, participant_id: 0, crypto };
     SGState::Connected(state)
 }
 
@@ -217,7 +221,10 @@ fn parse_console_status_works() {
         active_titles: DynArray::new(vec![
             packet::message::ActiveTitle {
                 title_id: 714681658,
-                title_disposition: 32771,
+                title_disposition: TitleDisposition {
+                    has_focus: true,
+                    location: TitleLocation::StartView
+                },
                 product_id: constants::uuid::NONE.clone(),
                 sandbox_id: constants::uuid::NONE.clone(),
                 aum: SGString::from_str(String::from("Xbox.Home_8wekyb3d8bbwe!Xbox.Home.Application"))
@@ -324,28 +331,32 @@ fn parse_media_state_works() {
         channel_id: 153
     };
 
+    let mut metadata = MediaMetadata::new();
+    metadata.insert(String::from("title"), String::new());
+
     let message = packet::message::MediaStateData {
         title_id: 274278798,
         aum_id: SGString::from_str(String::from("AIVDE_s9eep9cpjhg6g!App")),
         asset_id: SGString::from_str(String::new()),
-        media_type: 0,
-        sound_level: 2,
-        enabled_commands: 33758,
-        playback_status: 2,
+        media_type: MediaType::NoMedia,
+        sound_level: SoundLevel::Full,
+        enabled_commands: EnabledCommands::PLAY | EnabledCommands::PAUSE | EnabledCommands::PLAY_PAUSE_TOGGLE |
+            EnabledCommands::STOP | EnabledCommands::NEXT_TRACK | EnabledCommands::PREV_TRACK |
+            EnabledCommands::FAST_FORWARD | EnabledCommands::REWIND | EnabledCommands::SEEK,
+        playback_status: PlaybackStatus::Stopped,
         rate: 0.0,
         position: 0,
         media_start: 0,
         media_end: 0,
         min_seek: 0,
         max_seek: 0,
-        metadata: DynArray::new(vec![
-            packet::message::MediaStateMetadata {
-                name: SGString::from_str(String::from("title")),
-                value: SGString::from_str(String::new())
-            }
-        ])
+        metadata
     };
 
+    assert_eq!(message.enabled_commands.bits(), 33758);
+    assert!(message.enabled_commands.allows(MediaControlCommand::Seek));
+    assert_eq!(message.duration(), Duration::from_secs(0));
+
     test_message(data, Message::MediaState(message), header);
 }
 
@@ -497,12 +508,54 @@ fn parse_system_text_input_works() {
         selection_end: 4294967295,
         flags: 0,
         text_chunk_byte_start: 0,
-        text_chunk: SGString::from_str(String::from("h"))
+        text_chunk: SGString::from_str(String::from("h")),
+        delta: None
     };
 
     test_message(data, Message::SystemTextInput(message), header);
 }
 
+fn text_delta(offset: u32, delete_count: u32, insert_content: &str) -> TextDelta {
+    TextDelta {
+        offset,
+        delete_count,
+        insert_content: SGString::from_str(String::from(insert_content))
+    }
+}
+
+#[test]
+fn system_text_input_delta_round_trips() {
+    let input = SystemTextInputData {
+        session_id: 8,
+        base_version: 1,
+        submitted_version: 2,
+        total_text_byte_len: 5,
+        selection_start: 4294967295,
+        selection_end: 4294967295,
+        flags: 0,
+        text_chunk_byte_start: 0,
+        text_chunk: SGString::from_str(String::from("hello")),
+        delta: Some(vec![text_delta(0, 1, "j"), text_delta(5, 0, "!")])
+    };
+
+    let data = input.raw_bytes().unwrap();
+    assert_eq!(SystemTextInputData::from_raw_bytes(&data).unwrap(), input);
+
+    let mut text = String::from("hello");
+    assert!(input.apply_delta(&mut text));
+    assert_eq!(text, "jello!");
+}
+
+#[test]
+fn invalid_text_delta_is_rejected() {
+    let mut text = String::from("hä");
+
+    // Splits the ä in half
+    assert!(!TextDelta::apply_all(&[text_delta(2, 1, "a")], &mut text));
+    assert!(!TextDelta::apply_all(&[text_delta(0, 1, "j"), text_delta(3, 2, "")], &mut text));
+    assert_eq!(text, "hä");
+}
+
 #[test]
 fn repack_system_text_input_works() {
     let data = include_bytes!("data/message/system_text_input");
@@ -532,10 +585,10 @@ fn parse_system_touch_works() {
 
     let message = packet::message::TouchData {
         timestamp: 182459592,
-        active_titles: DynArray::new(vec![
+        touchpoints: DynArray::new(vec![
             packet::message::Touchpoint {
                 id: 1,
-                action: 1,
+                action: TouchAction::Down,
                 x: 244,
                 y: 255
             }
@@ -549,4 +602,36 @@ fn parse_system_touch_works() {
 fn repack_system_touch_works() {
     let data = include_bytes!("data/message/system_touch");
     test_repack(data);
-}
\ No newline at end of file
+}
+#[test]
+fn title_launch_uris_work() {
+    let launch = TitleLaunchData::title_id(0x3d705025, TitleLocation::Full);
+    assert_eq!(launch.uri.to_str(), "ms-xbl-3D705025://default/");
+    assert_eq!(launch.location, TitleLocation::Full);
+    assert_eq!(launch.launched_title_id(), Some(0x3d705025));
+
+    let launch = TitleLaunchData::aum("Microsoft.MicrosoftEdge_8wekyb3d8bbwe!MicrosoftEdge", TitleLocation::Snapped);
+    assert_eq!(launch.uri.to_str(), "appx:Microsoft.MicrosoftEdge_8wekyb3d8bbwe!MicrosoftEdge");
+    assert_eq!(launch.launched_title_id(), None);
+
+    let launch = TitleLaunchData::store_product("9WZDNCRFJ3TJ", TitleLocation::Default);
+    assert_eq!(launch.uri.to_str(), "ms-windows-store://pdp/?productid=9WZDNCRFJ3TJ");
+
+    let data = launch.raw_bytes().unwrap();
+    assert_eq!(&data[..2], &[0x00, 0x05]);
+    assert_eq!(TitleLaunchData::from_raw_bytes(&data).unwrap(), launch);
+}
+
+#[test]
+fn unknown_reported_values_are_kept() {
+    let disposition = TitleDisposition::from_raw_bytes(&[0x80, 0x09]).unwrap();
+    assert_eq!(disposition.location, TitleLocation::Unknown(9));
+    assert!(disposition.has_focus);
+    assert_eq!(disposition.raw_bytes().unwrap(), vec![0x80, 0x09]);
+
+    assert_eq!(MediaType::from_raw_bytes(&[0x00, 0x2a]).unwrap(), MediaType::Unknown(42));
+    assert_eq!(PlaybackStatus::from_raw_bytes(&[0x00, 0x03]).unwrap(), PlaybackStatus::Playing);
+    assert_eq!(SoundLevel::Unknown(7).raw_bytes().unwrap(), vec![0x00, 0x07]);
+    assert_eq!(TextResult::from_raw_bytes(&[0x00, 0x02]).unwrap(), TextResult::Unknown(2));
+    assert_eq!(PairingState::from_raw_bytes(&[0x00, 0x05]).unwrap(), PairingState::Unknown(5));
+}
@@ -0,0 +1,169 @@
+#![cfg(feature = "mock")]
+
+extern crate uuid;
+extern crate xbox_sg;
+
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+use xbox_sg::client::Client;
+use xbox_sg::constants;
+use xbox_sg::mock::{self, MockConsole, MockError};
+use xbox_sg::packet::{Packet, factory};
+use xbox_sg::packet::message::{GameDvrRecordData, Message, TitleLaunchData, TitleLocation};
+use xbox_sg::session::Session;
+use xbox_sg::session::channel::ServiceChannel;
+use xbox_sg::sgcrypto::Crypto;
+use xbox_sg::state::*;
+use xbox_sg::util::PublicKeyType;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn connected_client() -> (Client, mock::MockServer) {
+    let server = MockConsole::new().unwrap().spawn().unwrap();
+    let mut client = Client::new(server.addr()).unwrap();
+    client.connect(String::from("deadbeefdeadbeefde"), String::from("dummy_token"), TIMEOUT).unwrap();
+    (client, server)
+}
+
+#[test]
+fn discovery_works() {
+    let server = MockConsole::new().unwrap().name("Mock").spawn().unwrap();
+    let mut client = Client::new(server.addr()).unwrap();
+
+    let response = client.discover(TIMEOUT).unwrap();
+    assert_eq!(response.name.to_str(), "Mock");
+    assert_eq!(response.certificate.subject(), "FFFFFFFFFFF");
+}
+
+#[test]
+fn connect_works() {
+    let (mut client, server) = connected_client();
+
+    let state = client.state().ensure_connected().unwrap();
+    assert_eq!(state.connection_state, ConnectionState::Connected);
+    assert_eq!(state.participant_id, mock::PARTICIPANT_ID);
+
+    loop {
+        if let Packet::Message(_, Message::ConsoleStatus(_)) = client.recv_timeout(TIMEOUT).unwrap() {
+            break;
+        }
+    }
+    assert_eq!(client.session().console().version, (10, 0, 14393));
+
+    let console = server.stop();
+    assert_eq!(console.credentials(), ("deadbeefdeadbeefde", "dummy_token"));
+}
+
+#[test]
+fn larger_curves_work() {
+    for &key_type in [PublicKeyType::EcDhP384, PublicKeyType::EcDhP521].iter() {
+        let server = MockConsole::with_key_type(key_type).unwrap().spawn().unwrap();
+        let mut client = Client::new(server.addr()).unwrap();
+
+        client.connect(String::from("deadbeefdeadbeefde"), String::from("dummy_token"), TIMEOUT).unwrap();
+        assert_eq!(client.state().ensure_connected().unwrap().participant_id, mock::PARTICIPANT_ID);
+    }
+}
+
+#[test]
+fn long_tokens_are_put_back_together() {
+    let server = MockConsole::new().unwrap().spawn().unwrap();
+    let mut client = Client::new(server.addr()).unwrap();
+    let jwt = "eyJhbGciOiJSUzI1NiJ9.".repeat(150);
+
+    client.connect(String::from("deadbeefdeadbeefde"), jwt.clone(), TIMEOUT).unwrap();
+
+    let console = server.stop();
+    assert_eq!(console.credentials(), ("deadbeefdeadbeefde", &jwt[..]));
+}
+
+#[test]
+fn messages_are_acknowledged() {
+    let (mut client, _server) = connected_client();
+    let record = Message::GameDvrRecord(GameDvrRecordData {
+        start_time_delta: 0,
+        end_time_delta: 30
+    });
+    client.send_message(record, 0, true).unwrap();
+    assert!(client.session().poll_timeout().is_some());
+
+    loop {
+        if let Packet::Message(_, Message::Acknowledge(_)) = client.recv_timeout(TIMEOUT).unwrap() {
+            break;
+        }
+    }
+    assert!(client.session().poll_timeout().is_none());
+}
+
+#[test]
+fn channels_are_granted() {
+    let (mut client, _server) = connected_client();
+
+    assert_eq!(client.open_channel(ServiceChannel::SystemInput, TIMEOUT).unwrap(), 148);
+    assert_eq!(client.open_channel(ServiceChannel::SystemMedia, TIMEOUT).unwrap(), 149);
+}
+
+#[test]
+fn launches_without_a_status_find_the_title() {
+    let (mut client, _server) = connected_client();
+    assert!(!client.session().console().has_status());
+
+    let launch = TitleLaunchData::title_id(0x3d705025, TitleLocation::Full);
+    let title = client.launch_title(launch, TIMEOUT).unwrap();
+    assert_eq!(title.title_id, 0x3d705025);
+    assert!(title.title_disposition.has_focus);
+
+    let launch = TitleLaunchData::aum("Microsoft.MicrosoftEdge_8wekyb3d8bbwe!MicrosoftEdge", TitleLocation::Full);
+    assert_eq!(client.launch_title(launch, TIMEOUT).unwrap().title_id, mock::LAUNCHED_TITLE_ID);
+}
+
+#[test]
+fn dropped_datagrams_are_kept_as_last_error() {
+    let server = MockConsole::new().unwrap().spawn().unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(&[0xff, 0xff, 0x00], server.addr()).unwrap();
+
+    // The server handles datagrams in order, so the junk was dealt with once this is answered
+    let mut client = Client::new(server.addr()).unwrap();
+    client.discover(TIMEOUT).unwrap();
+
+    match server.stop().last_error() {
+        Some(&MockError::UnknownPacket) => {},
+        other => panic!("Expected UnknownPacket, got {:?}", other)
+    }
+}
+
+#[test]
+fn retransmissions_are_handled_once() {
+    let mut console = MockConsole::new().unwrap();
+    let mut session = Session::new();
+
+    let discovery = session.raw_bytes(&factory::discovery_request(constants::CLIENT_TYPE)).unwrap();
+    let certificate = match session.read(&console.handle(&discovery).unwrap()[0]).unwrap() {
+        Packet::DiscoveryResponse(_, data) => data.certificate,
+        _ => panic!("Wrong type")
+    };
+
+    let crypto = Crypto::from_certificate(&certificate).unwrap();
+    for request in session.connect_request(crypto, Uuid::new_v4(), String::new(), String::new()).unwrap() {
+        let data = session.raw_bytes(&request).unwrap();
+        for reply in console.handle(&data).unwrap() {
+            if let Some(packet @ Packet::ConnectResponse(..)) = session.receive(&reply).unwrap() {
+                assert!(session.handle_connect_response(&packet).unwrap());
+            }
+        }
+    }
+
+    let (_, datagrams) = session.start_channel(ServiceChannel::SystemInput, Instant::now()).unwrap();
+    assert_eq!(console.handle(&datagrams[0]).unwrap().len(), 2);
+
+    // Only acknowledged again, without a second channel
+    let replies = console.handle(&datagrams[0]).unwrap();
+    assert_eq!(replies.len(), 1);
+    match session.read(&replies[0]).unwrap() {
+        Packet::Message(_, Message::Acknowledge(ack)) => assert_eq!(ack.low_watermark, 1),
+        _ => panic!("Wrong type")
+    }
+}
@@ -0,0 +1,456 @@
+extern crate protocol;
+extern crate uuid;
+extern crate xbox_sg;
+
+use std::time::{Duration, Instant};
+
+use protocol::DynArray;
+use xbox_sg::constants;
+use xbox_sg::packet::Packet;
+use xbox_sg::packet::message::{AcknowledgeData, GameDvrRecordData, GamepadButtons, GamepadData, JsonData, MediaControlCommand, Message, SystemTextAcknowledgeData};
+use xbox_sg::packet::message::{StartChannelResponseData, TextResult};
+use xbox_sg::packet::message::{TextConfigurationData, TitleLaunchData, TitleLocation};
+use xbox_sg::session::{Session, SessionError};
+use xbox_sg::session::channel::ServiceChannel;
+use xbox_sg::session::console::ConsoleEvent;
+use xbox_sg::session::sensor::{ManualClock, SensorSample, SensorStream};
+use xbox_sg::session::touch::{Gesture, TouchTarget};
+use xbox_sg::sgcrypto;
+use xbox_sg::util::{PublicKeyType, SGString};
+use uuid::Uuid;
+
+fn connected_session() -> Session {
+    let mut session = Session::new();
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    session.connect_request(crypto, Uuid::nil(), String::new(), String::new()).unwrap();
+    session
+}
+
+fn acknowledgement(low_watermark: u32, processed: Vec<u32>, rejected: Vec<u32>) -> Vec<u8> {
+    // Shares the secret with the client, so it can stand in for the console
+    let mut console = connected_session();
+    let ack = Message::Acknowledge(AcknowledgeData {
+        low_watermark,
+        processed_list: DynArray::new(processed),
+        rejected_list: DynArray::new(rejected)
+    });
+    console.send_message(ack, constants::channel::ACK, false, Instant::now()).unwrap().remove(0)
+}
+
+fn record() -> Message {
+    Message::GameDvrRecord(GameDvrRecordData {
+        start_time_delta: 0,
+        end_time_delta: 30
+    })
+}
+
+#[test]
+fn receive_queues_acknowledgement() {
+    let mut session = connected_session();
+    let data = include_bytes!("data/message/console_status");
+
+    assert!(session.receive(data).unwrap().is_some());
+
+    let ack = session.poll_transmit(Instant::now()).unwrap().unwrap();
+    assert!(session.poll_transmit(Instant::now()).unwrap().is_none());
+
+    match session.read(&ack).unwrap() {
+        Packet::Message(header, Message::Acknowledge(ack)) => {
+            assert_eq!(header.channel_id, constants::channel::ACK);
+            assert!(!header.flags.need_ack);
+            assert_eq!(ack.processed_list.elements, vec![5]);
+        },
+        _ => panic!("Wrong type")
+    }
+}
+
+#[test]
+fn duplicates_are_acknowledged_but_dropped() {
+    let mut session = connected_session();
+    let data = include_bytes!("data/message/console_status");
+
+    assert!(session.receive(data).unwrap().is_some());
+    assert!(session.receive(data).unwrap().is_none());
+
+    assert!(session.poll_transmit(Instant::now()).unwrap().is_some());
+    assert!(session.poll_transmit(Instant::now()).unwrap().is_some());
+}
+
+#[test]
+fn unacknowledged_messages_are_retransmitted() {
+    let mut session = connected_session();
+    let now = Instant::now();
+    let sent = session.send_message(record(), constants::channel::CORE, true, now).unwrap().remove(0);
+
+    let wake = session.poll_timeout().unwrap();
+    assert!(wake > now);
+    assert!(session.poll_transmit(now).unwrap().is_none());
+    assert_eq!(session.poll_transmit(wake).unwrap(), Some(sent));
+}
+
+#[test]
+fn acknowledgement_settles_messages() {
+    let mut session = connected_session();
+    session.send_message(record(), constants::channel::CORE, true, Instant::now()).unwrap();
+
+    assert!(session.receive(&acknowledgement(0, vec![1], vec![])).unwrap().is_some());
+    assert_eq!(session.poll_timeout(), None);
+    assert!(session.poll_transmit(Instant::now() + Duration::from_secs(10)).unwrap().is_none());
+}
+
+#[test]
+fn rejected_messages_are_errors() {
+    let mut session = connected_session();
+    session.send_message(record(), constants::channel::CORE, true, Instant::now()).unwrap();
+
+    match session.receive(&acknowledgement(0, vec![], vec![1])) {
+        Err(SessionError::Rejected(rejected)) => assert_eq!(rejected, vec![1]),
+        _ => panic!("Expected a rejection")
+    }
+    assert_eq!(session.poll_timeout(), None);
+}
+
+fn large_json() -> Message {
+    let text = format!(r#"{{"padding":"{}"}}"#, "x".repeat(3000));
+    Message::Json(JsonData {
+        text: SGString::from_str(text)
+    })
+}
+
+#[test]
+fn large_messages_are_fragmented() {
+    let mut session = connected_session();
+    let datagrams = session.send_message(large_json(), 151, true, Instant::now()).unwrap();
+    assert_eq!(datagrams.len(), 3);
+
+    for (i, datagram) in datagrams.iter().enumerate() {
+        match session.read(datagram).unwrap() {
+            Packet::Message(header, Message::Fragment(msg_type, data)) => {
+                assert!(header.flags.is_fragment);
+                assert_eq!(header.sequence_number, i as u32 + 1);
+                assert_eq!(msg_type, xbox_sg::packet::message::MessageType::Json);
+                assert_eq!(data.sequence_begin, 1);
+                assert_eq!(data.sequence_end, 4);
+            },
+            _ => panic!("Wrong type")
+        }
+    }
+}
+
+#[test]
+fn out_of_order_fragments_are_reassembled() {
+    let mut console = connected_session();
+    let mut session = connected_session();
+    let mut datagrams = console.send_message(large_json(), 151, true, Instant::now()).unwrap();
+    datagrams.reverse();
+
+    assert!(session.receive(&datagrams[0]).unwrap().is_none());
+    assert!(session.receive(&datagrams[1]).unwrap().is_none());
+
+    match session.receive(&datagrams[2]).unwrap() {
+        Some(Packet::Message(header, message)) => {
+            assert!(!header.flags.is_fragment);
+            assert_eq!(header.channel_id, 151);
+            assert_eq!(message, large_json());
+        },
+        _ => panic!("Wrong type")
+    }
+
+    // Every fragment gets acknowledged on its own
+    for _ in 0..3 {
+        assert!(session.poll_transmit(Instant::now()).unwrap().is_some());
+    }
+}
+
+#[test]
+fn reassembled_messages_are_tracked() {
+    let mut console = connected_session();
+    let mut session = connected_session();
+    let config = Message::SystemTextConfiguration(TextConfigurationData {
+        session_id: 9,
+        buffer_version: 0,
+        options: 0,
+        input_scope: 0,
+        max_text_len: 0,
+        locale: SGString::from_str(String::from("en-US")),
+        prompt: SGString::from_str("x".repeat(3000))
+    });
+
+    let datagrams = console.send_message(config, 24, true, Instant::now()).unwrap();
+    assert_eq!(datagrams.len(), 3);
+    for datagram in datagrams.iter() {
+        session.receive(datagram).unwrap();
+    }
+
+    let prompt = session.text().prompt().unwrap();
+    assert_eq!(prompt.session_id, 9);
+    assert_eq!(prompt.prompt.len(), 3000);
+}
+
+#[cfg(feature = "json")]
+fn json_fragment(text: &str, offset: usize, len: usize) -> Message {
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+
+    let chunk = &text.as_bytes()[offset..offset + len];
+    let fragment = format!(
+        r#"{{"datagram_id":"1","datagram_size":"{}","fragment_offset":"{}","fragment_length":"{}","fragment_data":"{}"}}"#,
+        text.len(), offset, len, chunk.to_base64(STANDARD)
+    );
+    Message::Json(JsonData {
+        text: SGString::from_str(fragment)
+    })
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_fragments_are_reassembled() {
+    let mut console = connected_session();
+    let mut session = connected_session();
+    let text = r#"{"response":"GetConfiguration","params":{"device_id":"0"}}"#;
+
+    let first = console.send_message(json_fragment(text, 0, 20), 151, true, Instant::now()).unwrap().remove(0);
+    let second = console.send_message(json_fragment(text, 20, text.len() - 20), 151, true, Instant::now()).unwrap().remove(0);
+
+    assert!(session.receive(&second).unwrap().is_none());
+    match session.receive(&first).unwrap() {
+        Some(Packet::Message(header, Message::Json(data))) => {
+            assert_eq!(header.channel_id, 151);
+            let value: serde_json::Value = data.parse().unwrap();
+            assert_eq!(value["params"]["device_id"], "0");
+        },
+        _ => panic!("Wrong type")
+    }
+}
+
+#[test]
+fn start_channel_response_opens_channel() {
+    let mut session = connected_session();
+    let (request_id, _) = session.start_channel(ServiceChannel::SystemInput, Instant::now()).unwrap();
+    assert_eq!(request_id, 1);
+
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+    assert_eq!(session.channels().channel_id(ServiceChannel::SystemInput), Some(148));
+
+    let datagram = session.send_on(ServiceChannel::SystemInput, record(), false, Instant::now()).unwrap().remove(0);
+    match session.read(&datagram).unwrap() {
+        Packet::Message(header, _) => assert_eq!(header.channel_id, 148),
+        _ => panic!("Wrong type")
+    }
+}
+
+#[test]
+fn send_on_requires_open_channel() {
+    let mut session = connected_session();
+
+    match session.send_on(ServiceChannel::SystemMedia, record(), false, Instant::now()) {
+        Err(SessionError::ChannelNotOpen(ServiceChannel::SystemMedia)) => {},
+        _ => panic!("Expected ChannelNotOpen")
+    }
+}
+
+#[test]
+fn press_sends_press_and_release() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::SystemInput, Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    let datagrams = session.press(GamepadButtons::A | GamepadButtons::DPAD_UP, 1000, Instant::now()).unwrap();
+    assert_eq!(datagrams.len(), 2);
+
+    let reports: Vec<GamepadData> = datagrams.iter()
+        .map(|datagram| match session.read(datagram).unwrap() {
+            Packet::Message(_, Message::Gamepad(report)) => report,
+            _ => panic!("Wrong type")
+        })
+        .collect();
+
+    assert_eq!(reports[0].buttons, GamepadButtons::A | GamepadButtons::DPAD_UP);
+    assert_eq!(reports[1].buttons, GamepadButtons::empty());
+    assert!(reports[1].timestamp > reports[0].timestamp);
+}
+
+#[test]
+fn media_commands_carry_request_ids() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::SystemMedia, Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    let (play_id, _) = session.media_command(0x1234, MediaControlCommand::Play, Instant::now()).unwrap();
+    let (seek_id, datagrams) = session.media_seek(0x1234, Duration::from_secs(5), Instant::now()).unwrap();
+    assert_ne!(play_id, seek_id);
+    assert!(session.media().is_pending(seek_id));
+
+    match session.read(&datagrams[0]).unwrap() {
+        Packet::Message(header, Message::MediaCommand(command)) => {
+            assert_eq!(header.channel_id, 148);
+            assert_eq!(command.request_id, seek_id);
+            assert_eq!(command.command, MediaControlCommand::Seek);
+            assert_eq!(command.seek_position, Some(50_000_000));
+        },
+        _ => panic!("Wrong type")
+    }
+}
+
+#[test]
+fn text_session_works() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::SystemText, Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    match session.submit_text("hello", Instant::now()) {
+        Err(SessionError::NoTextSession) => {},
+        _ => panic!("Expected NoTextSession")
+    }
+
+    session.receive(include_bytes!("data/message/system_text_configuration")).unwrap();
+    assert_eq!(session.text().prompt().unwrap().session_id, 9);
+    assert_eq!(session.text().prompt().unwrap().locale, "de-DE");
+
+    let (version, datagrams) = session.submit_text("hello", Instant::now()).unwrap();
+    assert_eq!(version, 1);
+    match session.read(&datagrams[0]).unwrap() {
+        Packet::Message(_, Message::SystemTextInput(input)) => {
+            assert_eq!(input.session_id, 9);
+            assert_eq!(input.base_version, 0);
+            assert_eq!(input.submitted_version, 1);
+            assert_eq!(input.text_chunk, SGString::from_str(String::from("hello")));
+        },
+        _ => panic!("Wrong type")
+    }
+
+    let mut console = connected_session();
+    let ack = Message::SystemTextAcknowledge(SystemTextAcknowledgeData {
+        session_id: 9,
+        version_ack: 1
+    });
+    let ack = console.send_message(ack, 148, false, Instant::now()).unwrap().remove(0);
+    session.receive(&ack).unwrap();
+    assert!(!session.text().is_pending());
+
+    let datagrams = session.finish_text(TextResult::Accept, Instant::now()).unwrap();
+    match session.read(&datagrams[0]).unwrap() {
+        Packet::Message(_, Message::SystemTextDone(done)) => {
+            assert_eq!(done.session_id, 9);
+            assert_eq!(done.version, 1);
+        },
+        _ => panic!("Wrong type")
+    }
+    assert!(session.text().prompt().is_none());
+}
+
+#[test]
+fn text_limit_counts_characters() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::SystemText, Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    let mut console = connected_session();
+    let config = Message::SystemTextConfiguration(TextConfigurationData {
+        session_id: 9,
+        buffer_version: 0,
+        options: 0,
+        input_scope: 0,
+        max_text_len: 5,
+        locale: SGString::from_str(String::from("de-DE")),
+        prompt: SGString::from_str(String::from("Name"))
+    });
+    session.receive(&console.send_message(config, 148, false, Instant::now()).unwrap().remove(0)).unwrap();
+
+    // Seven bytes, but only five characters
+    assert!(session.submit_text("Jürgö", Instant::now()).is_ok());
+    match session.submit_text("Jürgön", Instant::now()) {
+        Err(SessionError::TextTooLong(6, 5)) => {},
+        _ => panic!("Expected TextTooLong")
+    }
+}
+
+#[test]
+fn touch_goes_to_target() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::SystemInput, Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    session.start_channel(ServiceChannel::Title(0x3d705025), Instant::now()).unwrap();
+    let mut console = connected_session();
+    let response = Message::StartChannelResponse(StartChannelResponseData {
+        channel_request_id: 2,
+        target_channel_id: 151,
+        result: 0
+    });
+    session.receive(&console.send_message(response, constants::channel::CORE, false, Instant::now()).unwrap().remove(0)).unwrap();
+
+    let report = Gesture::tap(10, 20).reports(0).remove(0);
+    let system = session.touch(report.clone(), TouchTarget::System, Instant::now()).unwrap().remove(0);
+    let title = session.touch(report.clone(), TouchTarget::Title(0x3d705025), Instant::now()).unwrap().remove(0);
+
+    match session.read(&system).unwrap() {
+        Packet::Message(header, Message::SystemTouch(data)) => {
+            assert_eq!(header.channel_id, 148);
+            assert!(!header.flags.need_ack);
+            assert_eq!(data, report);
+        },
+        _ => panic!("Wrong type")
+    }
+    match session.read(&title).unwrap() {
+        Packet::Message(header, Message::TitleTouch(data)) => {
+            assert_eq!(header.channel_id, 151);
+            assert!(!header.flags.need_ack);
+            assert_eq!(data, report);
+        },
+        _ => panic!("Wrong type")
+    }
+
+    match session.touch(report, TouchTarget::Title(0x1234), Instant::now()) {
+        Err(SessionError::ChannelNotOpen(ServiceChannel::Title(0x1234))) => {},
+        _ => panic!("Expected ChannelNotOpen")
+    }
+}
+
+#[test]
+fn sensor_data_goes_to_title_channel() {
+    let mut session = connected_session();
+    session.start_channel(ServiceChannel::Title(0x3d705025), Instant::now()).unwrap();
+    session.receive(include_bytes!("data/message/start_channel_response")).unwrap();
+
+    let clock = ManualClock::new();
+    let mut stream = SensorStream::with_clock(60, &clock);
+    let message = stream.stamp(SensorSample::Gyrometer { x: 1.0, y: 0.0, z: 0.0 }).unwrap();
+
+    let datagram = session.sensor(0x3d705025, message, Instant::now()).unwrap().remove(0);
+    match session.read(&datagram).unwrap() {
+        Packet::Message(header, Message::Gyrometer(data)) => {
+            assert_eq!(header.channel_id, 148);
+            assert_eq!(data.angular_velocity_x, 1.0);
+        },
+        _ => panic!("Wrong type")
+    }
+
+    match session.sensor(0x1234, SensorSample::Compass { magnetic_north: 0.0, true_north: 0.0 }.into_message(0), Instant::now()) {
+        Err(SessionError::ChannelNotOpen(ServiceChannel::Title(0x1234))) => {},
+        _ => panic!("Expected ChannelNotOpen")
+    }
+}
+
+#[test]
+fn console_status_is_kept() {
+    let mut session = connected_session();
+    assert!(session.console().active_titles.is_empty());
+
+    session.receive(include_bytes!("data/message/console_status")).unwrap();
+    assert_eq!(session.console().version, (10, 0, 14393));
+    match session.poll_event() {
+        Some(ConsoleEvent::TitleLaunched(title)) => assert_eq!(session.console().focused_title(), Some(&title)),
+        _ => panic!("Expected TitleLaunched")
+    }
+    assert!(session.poll_event().is_none());
+
+    let launch = TitleLaunchData::title_id(0x3d705025, TitleLocation::Full);
+    let datagram = session.launch_title(launch.clone(), Instant::now()).unwrap().remove(0);
+    match session.read(&datagram).unwrap() {
+        Packet::Message(header, Message::TitleLaunch(data)) => {
+            assert_eq!(header.channel_id, constants::channel::CORE);
+            assert_eq!(data, launch);
+        },
+        _ => panic!("Wrong type")
+    }
+}
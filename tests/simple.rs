@@ -8,8 +8,8 @@ use xbox_sg::util::*;
 use uuid::Uuid;
 
 fn new_connected_state() -> SGState {
-    let crypto = sgcrypto::tests::from_secret(include_bytes!("data/secret"));
-    let state = State{ connection_state: ConnectionState::Connecting, pairing_state: PairingState::NotPaired, crypto };
+    let crypto = sgcrypto::Crypto::from_secret(&sgcrypto::SharedSecret::from_bytes(PublicKeyType::EcDhP256, include_bytes!("data/secret")).unwrap());
+    let state = State{ connection_state: ConnectionState::Connecting, pairing_state: PairingState::NotPaired, participant_id: 0, crypto };
     SGState::Connected(state)
 }
 
@@ -57,13 +57,30 @@ fn parse_discovery_response_works() {
             assert_eq!(data.name, SGString::from_str(String::from("XboxOne")));
             assert_eq!(data.uuid, UUID::new(Uuid::parse_str("DE305D54-75B4-431B-ADB2-EB6B9E546014").unwrap()));
             assert_eq!(data.certificate.subject(), "FFFFFFFFFFF");
-            assert_eq!(data.certificate.public_key_type(), 4);
+            assert_eq!(data.certificate.public_key_type(), PublicKeyType::EcDhP256);
             // assert_eq!(data.certificate.elements.len(), 587); // todo: properly parse cert
         },
         _ => panic!("Wrong type")
     }
 }
 
+#[test]
+fn discovery_response_key_is_usable() {
+    let data = include_bytes!("data/discovery_response");
+
+    match packet::Packet::read(data, &SGState::Disconnected).unwrap() {
+        packet::Packet::DiscoveryResponse(_, data) => {
+            let certificate = data.certificate;
+            assert_eq!(certificate.public_key().len(), 64);
+            assert_eq!(certificate.public_key_point()[0], 0x04);
+
+            let crypto = sgcrypto::Crypto::from_certificate(&certificate).unwrap();
+            assert_eq!(crypto.key_type(), PublicKeyType::EcDhP256);
+        },
+        _ => panic!("Wrong type")
+    }
+}
+
 #[test]
 fn rebuild_discovery_response_works() {
     let data = include_bytes!("data/discovery_response");
@@ -72,34 +89,57 @@ fn rebuild_discovery_response_works() {
     assert_eq!(data.to_vec(), packet.raw_bytes(&SGState::Disconnected).unwrap());
 }
 
-// #[test]
-// fn parse_connect_request_unprotected_data_works() {
-//     let data = include_bytes!("data/connect_request");
-//     let crypto = ::sgcrypto::test::from_secret(include_bytes!("data/secret"));
-//     let packet = Packet::from_raw_bytes_protected(data, &crypto).unwrap();
-
-//     match packet {
-//         Packet::ConnectRequest(header, unprotected_data, protected_data) => {
-//             assert_eq!(header.pkt_type, packet::Type::ConnectRequest);
-//             assert_eq!(header.unprotected_payload_length, 98);
-//             assert_eq!(header.protected_payload_length, 47);
-//             assert_eq!(header.version, 2);
-
-//             assert_eq!(unprotected_data.sg_uuid, [222, 48, 93, 84, 117, 180, 67, 27, 173, 178, 235, 107, 158, 84, 96, 20]);
-//             assert_eq!(unprotected_data.public_key_type, 0);
-//             assert_eq!(unprotected_data.public_key_1, [255u8; 32]);
-//             assert_eq!(unprotected_data.public_key_2, [255u8; 32]);
-//             assert_eq!(unprotected_data.iv, [41, 121, 210, 94, 160, 61, 151, 245, 143, 70, 147, 10, 40, 139, 245, 210]);
-
-//             assert_eq!(protected_data.userhash, SGString::from_str(string::String::from("deadbeefdeadbeefde")));
-//             assert_eq!(protected_data.jwt, SGString::from_str(string::String::from("dummy_token")));
-//             assert_eq!(protected_data.connect_request_num, 0);
-//             assert_eq!(protected_data.connect_request_group_start, 0);
-//             assert_eq!(protected_data.connect_request_group_end, 2);
-//         },
-//         _ => panic!("Wrong type")
-//     }
-// }
+#[test]
+fn parse_connect_request_works() {
+    let data = include_bytes!("data/connect_request");
+    let sgstate = new_connected_state();
+    let packet = packet::Packet::read(data, &sgstate).unwrap();
+
+    match packet {
+        packet::Packet::ConnectRequest(header, unprotected_data, protected_data) => {
+            assert_eq!(header.pkt_type, packet::Type::ConnectRequest);
+            assert_eq!(header.unprotected_payload_length, 98);
+            assert_eq!(header.protected_payload_length, 47);
+            assert_eq!(header.version, 2);
+
+            assert_eq!(unprotected_data.sg_uuid, UUID::new(Uuid::parse_str("DE305D54-75B4-431B-ADB2-EB6B9E546014").unwrap()));
+            assert_eq!(unprotected_data.public_key, PublicKey::new(PublicKeyType::EcDhP256, vec![255u8; 64]).unwrap());
+            assert_eq!(unprotected_data.iv, [41, 121, 210, 94, 160, 61, 151, 245, 143, 70, 147, 10, 40, 139, 245, 210]);
+
+            assert_eq!(protected_data.userhash, SGString::from_str(String::from("deadbeefdeadbeefde")));
+            assert_eq!(protected_data.jwt, SGString::from_str(String::from("dummy_token")));
+            assert_eq!(protected_data.request_num, 0);
+            assert_eq!(protected_data.request_group_start, 0);
+            assert_eq!(protected_data.request_group_end, 2);
+        },
+        _ => panic!("Wrong type")
+    }
+}
+
+#[test]
+fn repack_connect_request_works() {
+    let data = include_bytes!("data/connect_request");
+    let sgstate = new_connected_state();
+    let packet = packet::Packet::read(data, &sgstate).unwrap();
+
+    assert_eq!(data.to_vec(), packet.raw_bytes(&sgstate).unwrap());
+}
+
+#[test]
+fn connect_request_needs_crypto() {
+    let data = include_bytes!("data/connect_request");
+    assert!(packet::Packet::read(data, &SGState::Disconnected).is_err());
+}
+
+#[test]
+fn short_connect_requests_are_rejected() {
+    let data = include_bytes!("data/connect_request");
+
+    match packet::Packet::read(&data[..20], &new_connected_state()) {
+        Err(packet::ReadError::TooShort(20)) => {},
+        other => panic!("Expected a short packet error, got {:?}", other)
+    }
+}
 
 #[test]
 fn parse_connect_response_works() {